@@ -0,0 +1,50 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use clap::Args;
+use mdrop::capture::{self, Annotation};
+use mdrop::protocol::hex;
+use mdrop::transport::REQUEST_ID_WRITE;
+
+#[derive(Debug, Args)]
+pub struct DecodeArgs {
+    /// usbmon capture in pcap format, ex. from `tcpdump -i usbmon3 -w capture.pcap`
+    capture: PathBuf,
+}
+
+pub fn run(args: DecodeArgs) -> Result<(), Box<dyn Error>> {
+    let file = File::open(&args.capture)?;
+    let transfers = capture::read_transfers(BufReader::new(file))?;
+    if transfers.is_empty() {
+        println!("No Moondrop transfers in capture");
+        return Ok(());
+    }
+    let mut unknown = 0;
+    for transfer in &transfers {
+        let direction = match transfer.request {
+            REQUEST_ID_WRITE => "OUT",
+            _ => "IN ",
+        };
+        let marker = match transfer.annotation {
+            Annotation::Unknown(_) => {
+                unknown += 1;
+                "!!"
+            }
+            _ => "  ",
+        };
+        println!(
+            "{:>12.6} {:03}:{:03} {direction} {:<20} {marker} {}",
+            transfer.timestamp.as_secs_f64(),
+            transfer.bus,
+            transfer.device,
+            hex(&transfer.data),
+            transfer.annotation,
+        );
+    }
+    if unknown > 0 {
+        println!("{unknown} transfer(s) marked with !! didn't decode and are worth investigating");
+    }
+    Ok(())
+}
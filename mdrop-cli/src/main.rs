@@ -1,13 +1,27 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use clap::{Args, Parser, Subcommand};
 use mdrop::Moondrop;
+use mdrop::auto_gain::AutoGain;
+use mdrop::caps::{ANY_PORT, CapMode, VolumeCaps};
 use mdrop::filter::Filter;
 use mdrop::gain::Gain;
 use mdrop::indicator_state::IndicatorState;
-use mdrop::volume::Volume;
+use mdrop::ramp::Cancel;
+use mdrop::selector::DeviceSelector;
+use mdrop::volume::{Volume, VolumeDelta};
 use tabled::Table;
 use tabled::settings::themes::ColumnNames;
 use tabled::settings::{Alignment, Style};
 
+use crate::decode::DecodeArgs;
+use crate::raw::RawArgs;
+
+mod decode;
+mod raw;
+
 #[derive(Debug, Parser)]
 #[command(name = "mdrop")]
 #[command(about = "A tool to control your Moondrop dongle", long_about = None)]
@@ -15,9 +29,29 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    /// specify target device, by using the USB bus number, to which the command should be directed, ex. `03:02`
+    /// specify target device, by bus:address (ex. `03:02`), USB port path (ex. `3-1.2`), product name or index from `mdrop devices`
     #[arg(short = 's', global = true)]
-    device: Option<String>,
+    device: Option<DeviceSelector>,
+
+    /// allow changing settings on Moondrop devices that mdrop hasn't been verified against
+    #[arg(long, global = true)]
+    allow_unknown: bool,
+
+    /// go over the volume caps set up with `mdrop caps`
+    #[arg(long = "override", global = true)]
+    override_caps: bool,
+
+    /// switch to High gain for loud volumes and back to Low for quiet ones when changing the volume
+    #[arg(long, global = true)]
+    auto_gain: bool,
+
+    /// how many dB louder High gain is than Low, needed by `--keep-loudness` and `--auto-gain`
+    #[arg(long, global = true, value_name = "DB", value_parser = parse_gain_offset)]
+    gain_offset: Option<f32>,
+
+    /// record USB control traffic into a pcap file, with a hex log next to it
+    #[arg(long, global = true, value_name = "PATH")]
+    trace_file: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -26,8 +60,23 @@ enum Commands {
     Get(GetArgs),
     /// Sets various values in your Moondrop dongle
     Set(SetArgs),
+    /// Changes the volume relative to the current one, or mutes it
+    #[command(subcommand)]
+    Volume(VolumeCommands),
+    /// Manages the volume caps, the loudest volume allowed per dongle and gain
+    Caps(CapsArgs),
     /// Lists all the Moondrop dongles connected to the PC
     Devices,
+    /// Prints the settings changed on the dongle, ex. with its buttons, until interrupted
+    Monitor {
+        /// time between two reads, ex. `250ms` or `1s`
+        #[arg(long, value_parser = parse_duration, default_value = "250ms")]
+        interval: Duration,
+    },
+    /// Sends a raw vendor command and prints the response
+    Raw(RawArgs),
+    /// Annotates the Moondrop traffic in a usbmon pcap capture
+    Decode(DecodeArgs),
 }
 
 #[derive(Debug, Args)]
@@ -41,7 +90,11 @@ enum GetCommands {
     /// Gets status for filter, gain, and indicator state
     All,
     /// Gets current hardware volume of Moondrop dongle
-    Volume,
+    Volume {
+        /// report the attenuation in dB instead of percent
+        #[arg(long)]
+        db: bool,
+    },
     /// Gets audio filter
     Filter,
     /// Gets gain on device to Low or High
@@ -61,80 +114,278 @@ enum SetCommands {
     /// Sets audio filter
     Filter { filter: Filter },
     /// Sets gain on device to Low or High
-    Gain { gain: Gain },
+    Gain {
+        gain: Gain,
+        /// move the volume the other way so the loudness stays the same
+        #[arg(long)]
+        keep_loudness: bool,
+    },
     /// Sets current hardware volume
     Volume {
-        /// Volume level between 0 and 100
-        #[arg(value_parser = clap::value_parser!(u32).range(0..=100))]
-        level: u32,
+        #[command(flatten)]
+        volume: VolumeArgs,
+        /// walk there one step at a time over the given time (ex. `2s`, `500ms`)
+        #[arg(long, value_parser = parse_duration)]
+        ramp: Option<Duration>,
     },
     /// Sets indicator state to On, Off(temp), or Off
     IndicatorState { state: IndicatorState },
 }
 
+#[derive(Debug, Subcommand)]
+enum VolumeCommands {
+    /// Turns the volume up
+    Up {
+        /// percent, or steps and dB when suffixed (ex. `2steps`, `1.5dB`)
+        #[arg(value_parser = parse_delta, default_value = "5")]
+        amount: VolumeDelta,
+    },
+    /// Turns the volume down
+    Down {
+        /// percent, or steps and dB when suffixed (ex. `2steps`, `1.5dB`)
+        #[arg(value_parser = parse_delta, default_value = "5")]
+        amount: VolumeDelta,
+    },
+    /// Turns the volume all the way down, remembering the current volume
+    Mute,
+    /// Restores the volume from before muting
+    Unmute,
+    /// Unmutes when muted, mutes otherwise
+    ToggleMute,
+}
+
+#[derive(Debug, Args)]
+struct CapsArgs {
+    #[command(subcommand)]
+    command: Option<CapsCommands>,
+}
+
+#[derive(Debug, Subcommand)]
+enum CapsCommands {
+    /// Shows the caps and what happens to volumes over them
+    List,
+    /// Caps the volume at the given gain
+    Set {
+        gain: Gain,
+        /// loudest volume allowed, in percent or dB (ex. `60`, `-- -30dB`)
+        #[arg(allow_hyphen_values = true)]
+        volume: Volume,
+        /// only cap the dongle at this USB port path (ex. `3-1.2`), every dongle otherwise
+        #[arg(long, default_value = ANY_PORT)]
+        port: String,
+    },
+    /// Removes the cap at the given gain
+    Remove {
+        gain: Gain,
+        /// the USB port path the cap was set for
+        #[arg(long, default_value = ANY_PORT)]
+        port: String,
+    },
+    /// Sets whether volumes over their cap are lowered to it or refused
+    Mode { mode: CapMode },
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+struct VolumeArgs {
+    /// Volume level between 0 and 100, or attenuation in dB (ex. `-- -18dB`)
+    #[arg(allow_hyphen_values = true)]
+    level: Option<Volume>,
+    /// Hardware step between 0 (loudest) and 112 (quietest), reaches every level the dongle has
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=0x70))]
+    step: Option<u8>,
+    /// Loudness in dB relative to the loudest volume at Low gain, up to `--gain-offset` above 0 at High gain (ex. `--loudness=+3dB`)
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_db, conflicts_with = "ramp")]
+    loudness: Option<f32>,
+}
+
+impl VolumeArgs {
+    fn volume(&self) -> Volume {
+        match (self.level, self.step) {
+            (_, Some(step)) => Volume::from_step(step),
+            (Some(level), None) => level,
+            (None, None) => unreachable!("clap requires one of them, `--loudness` isn't a volume"),
+        }
+    }
+}
+
+/// Parses seconds or milliseconds, ex. `2s`, `1.5s` or `500ms`
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (value, scale) = match s.strip_suffix("ms") {
+        Some(ms) => (ms, 1000.0),
+        None => (s.strip_suffix('s').unwrap_or(s), 1.0),
+    };
+    let value: f64 = value.trim().parse().map_err(|e| format!("{e}"))?;
+    Duration::try_from_secs_f64(value / scale).map_err(|e| format!("{e}"))
+}
+
+/// Parses an amount of dB, with or without the unit
+fn parse_db(s: &str) -> Result<f32, String> {
+    let s = s.trim();
+    let db = s
+        .strip_suffix("dB")
+        .or_else(|| s.strip_suffix("db"))
+        .unwrap_or(s);
+    match db.trim().parse::<f32>() {
+        Ok(db) if db.is_finite() => Ok(db),
+        Ok(_) => Err("not a number of dB".to_string()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Parses a positive amount of dB, with or without the unit
+fn parse_gain_offset(s: &str) -> Result<f32, String> {
+    match parse_db(s)? {
+        db if db > 0.0 => Ok(db),
+        _ => Err("the offset has to be a positive number of dB".to_string()),
+    }
+}
+
+/// Parses a positive amount of percent, steps or dB
+fn parse_delta(s: &str) -> Result<VolumeDelta, String> {
+    let s = s.trim();
+    let delta = if let Some(db) = s.strip_suffix("dB").or_else(|| s.strip_suffix("db")) {
+        VolumeDelta::Db(db.trim().parse().map_err(|e| format!("{e}"))?)
+    } else if let Some(steps) = s.strip_suffix("steps").or_else(|| s.strip_suffix("step")) {
+        VolumeDelta::Steps(steps.trim().parse().map_err(|e| format!("{e}"))?)
+    } else {
+        VolumeDelta::Percent(
+            s.trim_end_matches('%')
+                .parse()
+                .map_err(|e| format!("{e}"))?,
+        )
+    };
+    match delta {
+        VolumeDelta::Percent(v) | VolumeDelta::Steps(v) if v < 0 => {
+            Err("amount can't be negative".to_string())
+        }
+        VolumeDelta::Db(v) if !v.is_finite() => Err(format!("{v} dB is not an amount")),
+        VolumeDelta::Db(v) if v < 0.0 => Err("amount can't be negative".to_string()),
+        delta => Ok(delta),
+    }
+}
+
+/// The same change in the other direction
+fn negate(delta: VolumeDelta) -> VolumeDelta {
+    match delta {
+        VolumeDelta::Percent(v) => VolumeDelta::Percent(-v),
+        VolumeDelta::Steps(v) => VolumeDelta::Steps(-v),
+        VolumeDelta::Db(v) => VolumeDelta::Db(-v),
+    }
+}
+
 fn main() {
     env_logger::init();
 
     let args = Cli::parse();
     log::debug!("Device: {:?}", args.device);
 
-    let mut moondrop = Moondrop::new();
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Cli) -> Result<(), Box<dyn Error>> {
+    // works on a file, no need to go through the USB devices
+    if let Commands::Decode(decode) = args.command {
+        return decode::run(decode);
+    }
+    // only edits the config file
+    if let Commands::Caps(caps) = args.command {
+        return caps_command(caps.command.unwrap_or(CapsCommands::List));
+    }
+
+    let mut moondrop = match &args.trace_file {
+        Some(path) => Moondrop::with_trace(path)?,
+        None => Moondrop::new()?,
+    };
+    moondrop.set_allow_unknown(args.allow_unknown);
+    moondrop.set_cap_override(args.override_caps);
+    moondrop.set_gain_offset(args.gain_offset);
+    let auto_gain = args.auto_gain;
+    if auto_gain {
+        moondrop.set_auto_gain(Some(AutoGain::default()))?;
+    }
+    let selector = args.device.unwrap_or_default();
 
     match args.command {
         Commands::Get(get) => {
             let get_cmd = get.command.unwrap_or(GetCommands::All);
             match get_cmd {
                 GetCommands::All => {
-                    if let Some(dongle) = moondrop.get_all() {
-                        let table = Table::new([dongle])
-                            .with(Style::sharp().remove_horizontals())
-                            .with(ColumnNames::default().alignment(Alignment::center()))
-                            .to_string();
-                        println!("{table}");
-                    } else {
-                        println!("No Moondrop dongle connected.");
-                    }
+                    let dongle = moondrop.get_all(&selector)?;
+                    let table = Table::new([dongle])
+                        .with(Style::sharp().remove_horizontals())
+                        .with(ColumnNames::default().alignment(Alignment::center()))
+                        .to_string();
+                    println!("{table}");
                 }
-                GetCommands::Volume => {
-                    if let Some(volume) = moondrop.get_volume() {
-                        println!("Volume: {}", volume);
-                    } else {
-                        println!("No Moondrop dongle connected.");
+                GetCommands::Volume { db } => {
+                    let volume = moondrop.get_volume(&selector)?;
+                    match db {
+                        true => println!("Volume: {volume:#}"),
+                        false => println!("Volume: {volume}"),
                     }
                 }
-                GetCommands::Filter => {
-                    if let Some(filter) = moondrop.get_filter() {
-                        println!("Filter: {filter}")
-                    } else {
-                        println!("No Moondrop dongle connected.");
-                    }
+                GetCommands::Filter => println!("Filter: {}", moondrop.get_filter(&selector)?),
+                GetCommands::Gain => println!("Gain: {}", moondrop.get_gain(&selector)?),
+                GetCommands::IndicatorState => {
+                    println!(
+                        "Indicator State: {}",
+                        moondrop.get_indicator_state(&selector)?
+                    )
                 }
-                GetCommands::Gain => {
-                    if let Some(gain) = moondrop.get_gain() {
-                        println!("Gain: {gain}");
-                    } else {
-                        println!("No Moondrop dongle connected.");
-                    }
+            }
+        }
+        Commands::Set(set) => {
+            match set.command {
+                SetCommands::Filter { filter } => moondrop.set_filter(&selector, filter)?,
+                SetCommands::Gain {
+                    gain,
+                    keep_loudness,
+                } => {
+                    moondrop.set_keep_loudness(keep_loudness);
+                    moondrop.set_gain(&selector, gain)?
                 }
-                GetCommands::IndicatorState => {
-                    if let Some(state) = moondrop.get_indicator_state() {
-                        println!("Indicator State: {state}");
-                    } else {
-                        println!("No Moondrop dongle connected.");
-                    }
+                SetCommands::Volume {
+                    volume,
+                    ramp: Some(duration),
+                } => moondrop.ramp_volume(&selector, volume.volume(), duration, &Cancel::new())?,
+                SetCommands::Volume { volume, ramp: None } => match volume.loudness {
+                    Some(loudness) => moondrop.set_loudness(&selector, loudness)?,
+                    None => moondrop.set_volume(&selector, volume.volume())?,
+                },
+                SetCommands::IndicatorState { state } => {
+                    moondrop.set_indicator_state(&selector, state)?
+                }
+            };
+        }
+        Commands::Volume(command) => {
+            let info = match command {
+                VolumeCommands::Up { amount } => moondrop.adjust_volume(&selector, amount)?,
+                VolumeCommands::Down { amount } => {
+                    moondrop.adjust_volume(&selector, negate(amount))?
                 }
+                VolumeCommands::Mute => moondrop.mute(&selector)?,
+                VolumeCommands::Unmute => moondrop.unmute(&selector)?,
+                VolumeCommands::ToggleMute => moondrop.toggle_mute(&selector)?,
+            };
+            match auto_gain {
+                true => println!("Volume: {} at {} gain", info.volume, info.gain),
+                false => println!("Volume: {}", info.volume),
             }
         }
-        Commands::Set(set) => match set.command {
-            SetCommands::Filter { filter } => moondrop.set_filter(filter),
-            SetCommands::Gain { gain } => moondrop.set_gain(gain),
-            SetCommands::Volume { level } => moondrop.set_volume(Volume::new(level)),
-            SetCommands::IndicatorState { state } => moondrop.set_indicator_state(state),
-        },
         Commands::Devices => {
-            let dongles = moondrop.detect();
+            let dongles = moondrop.detect(&selector)?;
             if !dongles.is_empty() {
-                let table = Table::new(dongles)
+                // indices are only meaningful for `-s` when the whole list is shown
+                let mut table = match selector {
+                    DeviceSelector::Any => Table::builder(dongles).index().build(),
+                    _ => Table::new(dongles),
+                };
+                let table = table
                     .with(Style::sharp().remove_horizontals())
                     .with(ColumnNames::default().alignment(Alignment::center()))
                     .to_string();
@@ -143,5 +394,46 @@ fn main() {
                 println!("No devices present");
             }
         }
+        Commands::Monitor { interval } => {
+            for event in moondrop.monitor(&selector, interval) {
+                let port = event.device.port_path();
+                for change in event.changes() {
+                    println!("{port} {change}");
+                }
+            }
+        }
+        Commands::Raw(raw) => raw::run(&moondrop, &selector, raw)?,
+        Commands::Decode(_) | Commands::Caps(_) => unreachable!(),
+    }
+    Ok(())
+}
+
+fn caps_command(command: CapsCommands) -> Result<(), Box<dyn Error>> {
+    let path = VolumeCaps::default_path().ok_or("no config directory, set $HOME or $MDROP_CAPS")?;
+    let mut caps = VolumeCaps::load(&path).map_err(mdrop::Error::Caps)?;
+    match command {
+        CapsCommands::List => {
+            if caps.caps().is_empty() {
+                println!("No volume caps in {}", path.display());
+                return Ok(());
+            }
+            println!("Over the cap: {}", caps.mode);
+            for cap in caps.caps() {
+                println!(
+                    "{} {} gain: {} ({:#})",
+                    cap.port, cap.gain, cap.volume, cap.volume
+                );
+            }
+            return Ok(());
+        }
+        CapsCommands::Set { gain, volume, port } => caps.set(&port, gain, volume),
+        CapsCommands::Remove { gain, port } => {
+            if !caps.remove(&port, gain) {
+                return Err(format!("no cap for {port} at {gain} gain").into());
+            }
+        }
+        CapsCommands::Mode { mode } => caps.mode = mode,
     }
+    caps.save(&path)?;
+    Ok(())
 }
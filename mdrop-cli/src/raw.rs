@@ -0,0 +1,109 @@
+use std::error::Error;
+
+use clap::Args;
+use mdrop::Moondrop;
+use mdrop::protocol::{self, hex};
+use mdrop::selector::DeviceSelector;
+use mdrop::transport::{REQUEST_ID_READ, REQUEST_ID_WRITE, REQUEST_INDEX, REQUEST_VALUE};
+
+#[derive(Debug, Args)]
+pub struct RawArgs {
+    /// command bytes in hex, ex. `c0 a5 a3` or `c0a5a3`
+    #[arg(required = true, value_parser = parse_hex)]
+    bytes: Vec<Vec<u8>>,
+
+    /// number of response bytes to read, 0 to only send the command
+    #[arg(short, long, default_value_t = protocol::RESPONSE_LEN as u16)]
+    read: u16,
+
+    /// only print the control transfers that would be issued
+    #[arg(long)]
+    dry_run: bool,
+
+    /// allow sending commands that may change the device settings
+    #[arg(long)]
+    force: bool,
+}
+
+pub fn run(
+    moondrop: &Moondrop,
+    selector: &DeviceSelector,
+    args: RawArgs,
+) -> Result<(), Box<dyn Error>> {
+    let cmd = args.bytes.concat();
+    if args.dry_run {
+        println!(
+            "control out: vendor, other, request {REQUEST_ID_WRITE:#04x}, value {REQUEST_VALUE:#06x}, index {REQUEST_INDEX:#06x}, data {}",
+            hex(&cmd)
+        );
+        if args.read > 0 {
+            println!(
+                "control in:  vendor, other, request {REQUEST_ID_READ:#04x}, value {REQUEST_VALUE:#06x}, index {REQUEST_INDEX:#06x}, length {}",
+                args.read
+            );
+        }
+        return Ok(());
+    }
+    if !protocol::is_query(&cmd) && !args.force {
+        return Err(format!(
+            "{} may change device settings, pass --force to send it",
+            hex(&cmd)
+        )
+        .into());
+    }
+
+    let response = moondrop.raw_command(selector, &cmd, args.read)?;
+    hexdump(&response);
+    Ok(())
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s
+        .split([' ', ',', ':'])
+        .map(|b| b.trim_start_matches("0x").trim_start_matches("0X"))
+        .collect();
+    // from_str_radix alone would take a sign, ex. `+f`
+    if digits.is_empty()
+        || !digits.len().is_multiple_of(2)
+        || !digits.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(format!("`{s}` is not a sequence of hex bytes"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("`{s}` is not a sequence of hex bytes"))
+}
+
+fn hexdump(data: &[u8]) {
+    if data.is_empty() {
+        println!("(no response)");
+    }
+    for (i, line) in data.chunks(16).enumerate() {
+        let ascii: String = line
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        println!("{:04x}  {:<47}  {ascii}", i * 16, hex(line));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_bytes() {
+        for s in ["c0 a5 a3", "c0a5a3", "0xc0,0xA5:0Xa3"] {
+            assert_eq!(parse_hex(s), Ok(vec![0xc0, 0xa5, 0xa3]), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_anything_but_pairs_of_hex_digits() {
+        for s in ["", "c", "c0a", "+f", "c0 +f", "-1", "0g", "c0 é"] {
+            assert!(parse_hex(s).is_err(), "{s:?}");
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use futures_lite::future;
+use mdrop::device::{BusAddress, DeviceDescriptor, MoondropDevice};
+use mdrop::mock::{Registers, Reply};
+use mdrop::protocol::{self, RESPONSE_LEN};
+use mdrop::transport::{Backend, TransferFuture, Transport};
+use mdrop::{DAWN_PRO_PID, Error, MOONDROP_VID, Result};
+
+pub mod usbip;
+
+/// A failure the emulator can be told to produce
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Stalls the next transfer
+    Stall,
+    /// Answers the next read with only this many bytes
+    ShortRead(usize),
+    /// Drops off the bus, every transfer fails until [`Emulator::reconnect`]
+    Disconnect,
+    /// Acknowledges the next write without applying it
+    DropWrite,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    registers: Registers,
+    response: Option<Vec<u8>>,
+    faults: VecDeque<Fault>,
+    disconnected: bool,
+}
+
+/// Emulated Dawn Pro, speaking the vendor protocol on top of its registers
+#[derive(Debug, Default)]
+pub struct Emulator {
+    state: Mutex<State>,
+}
+
+impl Emulator {
+    pub fn new(registers: Registers) -> Self {
+        Self {
+            state: Mutex::new(State {
+                registers,
+                ..State::default()
+            }),
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.state.lock().unwrap().registers
+    }
+
+    pub fn set_registers(&self, registers: Registers) {
+        self.state.lock().unwrap().registers = registers;
+    }
+
+    /// Queues `fault` to be produced by an upcoming transfer, faults are produced in order
+    pub fn inject(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.state.lock().unwrap().disconnected
+    }
+
+    /// Comes back after a [`Fault::Disconnect`]
+    pub fn reconnect(&self) {
+        self.state.lock().unwrap().disconnected = false;
+    }
+
+    /// Handles the data stage of a vendor control-out transfer
+    pub fn control_out(&self, data: &[u8]) -> std::result::Result<(), Fault> {
+        let mut state = self.state.lock().unwrap();
+        let query = protocol::is_query(data);
+        match state.fault(|f| match f {
+            Fault::ShortRead(_) => false,
+            Fault::DropWrite => !query,
+            Fault::Stall | Fault::Disconnect => true,
+        }) {
+            Some(Fault::DropWrite) => {
+                log::debug!("emulator: dropping {data:02x?}");
+                return Ok(());
+            }
+            Some(fault) => return Err(fault),
+            None if state.disconnected => return Err(Fault::Disconnect),
+            None => {}
+        }
+        state.response = None;
+        match state.registers.handle(data) {
+            Reply::Response(response) => state.response = Some(response),
+            Reply::Applied => {}
+            Reply::Unknown => {
+                log::warn!("emulator: stalling unknown command {data:02x?}");
+                return Err(Fault::Stall);
+            }
+        }
+        log::debug!("emulator: {data:02x?} -> {:?}", state.registers);
+        Ok(())
+    }
+
+    /// Handles a vendor control-in transfer of up to `length` bytes
+    pub fn control_in(&self, length: u16) -> std::result::Result<Vec<u8>, Fault> {
+        let mut state = self.state.lock().unwrap();
+        let short = match state.fault(|f| f != Fault::DropWrite) {
+            Some(Fault::ShortRead(len)) => Some(len),
+            Some(fault) => return Err(fault),
+            None if state.disconnected => return Err(Fault::Disconnect),
+            None => None,
+        };
+        // reads without a preceding query are refused by the hardware as well
+        let Some(mut response) = state.response.take() else {
+            return Err(Fault::Stall);
+        };
+        debug_assert_eq!(response.len(), RESPONSE_LEN);
+        response.truncate(short.unwrap_or(RESPONSE_LEN).min(length as usize));
+        Ok(response)
+    }
+}
+
+impl State {
+    /// Pops the next queued fault if `applies` to the transfer at hand
+    fn fault(&mut self, applies: impl Fn(Fault) -> bool) -> Option<Fault> {
+        let fault = *self.faults.front()?;
+        if !applies(fault) {
+            return None;
+        }
+        self.faults.pop_front();
+        if fault == Fault::Disconnect {
+            self.disconnected = true;
+        }
+        Some(fault)
+    }
+}
+
+fn error(fault: Fault) -> Error {
+    match fault {
+        Fault::Stall => Error::Stall,
+        Fault::Disconnect => Error::DeviceGone,
+        Fault::ShortRead(_) | Fault::DropWrite => unreachable!("never fails the transfer"),
+    }
+}
+
+/// In-process [`Transport`] backed by an [`Emulator`]
+#[derive(Debug)]
+pub struct EmulatedTransport {
+    emulator: Arc<Emulator>,
+}
+
+impl EmulatedTransport {
+    pub fn new(emulator: Arc<Emulator>) -> Self {
+        Self { emulator }
+    }
+}
+
+impl Transport for EmulatedTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        Box::pin(future::ready(
+            self.emulator.control_out(data).map_err(error),
+        ))
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        Box::pin(future::ready(
+            self.emulator.control_in(length).map_err(error),
+        ))
+    }
+}
+
+/// [`Backend`] listing the attached emulators, disconnected ones disappear from the bus
+#[derive(Debug, Default)]
+pub struct EmulatorBackend {
+    emulators: Vec<(BusAddress, Arc<Emulator>)>,
+}
+
+impl EmulatorBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plugs in `emulator` and returns its (fake) bus address
+    pub fn attach(&mut self, emulator: Arc<Emulator>) -> BusAddress {
+        let address = BusAddress::new(1, self.emulators.len() as u8 + 1);
+        self.emulators.push((address, emulator));
+        address
+    }
+}
+
+impl Backend for EmulatorBackend {
+    fn enumerate(&self) -> Result<Vec<MoondropDevice>> {
+        let devices = self
+            .emulators
+            .iter()
+            .filter(|(_, emulator)| emulator.is_connected())
+            .map(|(address, emulator)| {
+                let descriptor = DeviceDescriptor {
+                    vendor_id: MOONDROP_VID,
+                    product_id: DAWN_PRO_PID,
+                    product: Some(usbip::PRODUCT.to_string()),
+                    address: *address,
+                    port_chain: vec![address.address],
+                };
+                let transport = EmulatedTransport::new(emulator.clone());
+                MoondropDevice::new(descriptor, Arc::new(transport))
+            })
+            .collect();
+        Ok(devices)
+    }
+}
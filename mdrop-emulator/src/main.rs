@@ -0,0 +1,50 @@
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::Arc;
+
+use clap::Parser;
+use mdrop::mock::Registers;
+use mdrop_emulator::{Emulator, usbip};
+
+#[derive(Debug, Parser)]
+#[command(name = "mdrop-emulator")]
+#[command(about = "Emulates a Moondrop Dawn Pro over USB/IP", long_about = None)]
+#[command(
+    after_help = "Attach it with `sudo modprobe vhci-hcd && sudo usbip attach -r 127.0.0.1 -b 1-1`"
+)]
+struct Cli {
+    /// TCP port to listen on, on localhost only
+    #[arg(short, long, default_value_t = usbip::DEFAULT_PORT)]
+    port: u16,
+
+    /// initial raw volume register, 0x00 being the loudest and 0x70 the quietest
+    #[arg(long, default_value_t = 0x38)]
+    volume: u8,
+}
+
+fn main() {
+    env_logger::init();
+
+    let args = Cli::parse();
+    let emulator = Arc::new(Emulator::new(Registers {
+        volume: args.volume,
+        ..Registers::default()
+    }));
+
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, args.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to listen on port {}: {e}", args.port);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Emulated {} exported as {} on 127.0.0.1:{}",
+        usbip::PRODUCT,
+        usbip::BUS_ID,
+        args.port
+    );
+    if let Err(e) = usbip::serve(listener, emulator) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,278 @@
+//! Minimal USB/IP server exposing an [`Emulator`] as a real USB device.
+//!
+//! Only what's needed to get the device enumerated by `vhci-hcd` and driven by `nusb` is
+//! implemented: the device list, importing, and control transfers on endpoint 0.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use mdrop::transport::{REQUEST_ID_READ, REQUEST_ID_WRITE, REQUEST_INDEX};
+use mdrop::{DAWN_PRO_PID, MOONDROP_VID};
+
+use crate::{Emulator, Fault};
+
+pub const DEFAULT_PORT: u16 = 3240;
+
+pub const MANUFACTURER: &str = "MOONDROP";
+pub const PRODUCT: &str = "MOONDROP Dawn Pro";
+const SERIAL: &str = "EMULATOR";
+
+/// Bus id the device is exported as, `usbip attach -r 127.0.0.1 -b 1-1`
+pub const BUS_ID: &str = "1-1";
+const SYSFS_PATH: &str = "/sys/devices/platform/mdrop-emulator/usb1/1-1";
+const BUS_NUM: u32 = 1;
+const DEV_NUM: u32 = 2;
+const SPEED_HIGH: u32 = 3;
+const BCD_DEVICE: u16 = 0x0100;
+
+const USBIP_VERSION: u16 = 0x0111;
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+
+const CMD_SUBMIT: u32 = 1;
+const CMD_UNLINK: u32 = 2;
+const RET_SUBMIT: u32 = 3;
+const RET_UNLINK: u32 = 4;
+const DIR_IN: u32 = 1;
+
+const EPIPE: i32 = 32;
+
+const REQUEST_GET_STATUS: u8 = 0x00;
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const REQUEST_GET_CONFIGURATION: u8 = 0x08;
+const REQUEST_SET_CONFIGURATION: u8 = 0x09;
+const REQUEST_SET_INTERFACE: u8 = 0x0B;
+
+const DESCRIPTOR_DEVICE: u8 = 0x01;
+const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+const DESCRIPTOR_STRING: u8 = 0x03;
+
+/// Accepts USB/IP clients on `listener` until it fails, each connection is served on its own thread
+pub fn serve(listener: TcpListener, emulator: Arc<Emulator>) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let emulator = emulator.clone();
+        thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            log::info!("usbip: connection from {peer:?}");
+            if let Err(e) = Connection::new(stream, emulator).run() {
+                log::info!("usbip: connection from {peer:?} closed: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+struct Setup {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+}
+
+struct Connection {
+    stream: TcpStream,
+    emulator: Arc<Emulator>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, emulator: Arc<Emulator>) -> Self {
+        Self { stream, emulator }
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        let mut header = [0; 8];
+        self.stream.read_exact(&mut header)?;
+        match u16::from_be_bytes([header[2], header[3]]) {
+            OP_REQ_DEVLIST => {
+                let mut reply = op_header(OP_REP_DEVLIST, 0);
+                reply.extend_from_slice(&1u32.to_be_bytes());
+                reply.extend_from_slice(&device_info());
+                // the single vendor specific interface
+                reply.extend_from_slice(&[0xff, 0x00, 0x00, 0x00]);
+                self.stream.write_all(&reply)
+            }
+            OP_REQ_IMPORT => {
+                let mut bus_id = [0; 32];
+                self.stream.read_exact(&mut bus_id)?;
+                let requested = String::from_utf8_lossy(&bus_id);
+                if requested.trim_end_matches('\0') != BUS_ID {
+                    return self.stream.write_all(&op_header(OP_REP_IMPORT, 1));
+                }
+                let mut reply = op_header(OP_REP_IMPORT, 0);
+                reply.extend_from_slice(&device_info());
+                self.stream.write_all(&reply)?;
+                self.urbs()
+            }
+            code => Err(invalid(format!("unknown operation {code:#06x}"))),
+        }
+    }
+
+    /// Serves URBs of an imported device until the client goes away
+    fn urbs(&mut self) -> io::Result<()> {
+        loop {
+            let mut header = [0; 48];
+            self.stream.read_exact(&mut header)?;
+            let word = |i: usize| u32::from_be_bytes(header[i..i + 4].try_into().unwrap());
+            let (command, seqnum, direction, ep) = (word(0), word(4), word(12), word(16));
+            match command {
+                CMD_SUBMIT => {
+                    let length = word(24) as usize;
+                    let mut data = vec![0; if direction == DIR_IN { 0 } else { length }];
+                    self.stream.read_exact(&mut data)?;
+                    let setup = Setup {
+                        request_type: header[40],
+                        request: header[41],
+                        value: u16::from_le_bytes([header[42], header[43]]),
+                        index: u16::from_le_bytes([header[44], header[45]]),
+                        length: u16::from_le_bytes([header[46], header[47]]),
+                    };
+                    let result = match ep {
+                        0 => self.control(&setup, &data),
+                        _ => Err(Fault::Stall),
+                    };
+                    let (status, response) = match result {
+                        Ok(response) => (0, response),
+                        Err(Fault::Disconnect) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::ConnectionAborted,
+                                "emulator disconnected",
+                            ));
+                        }
+                        Err(_) => (-EPIPE, Vec::new()),
+                    };
+                    let actual = match direction {
+                        DIR_IN => response.len(),
+                        _ if status == 0 => data.len(),
+                        _ => 0,
+                    };
+                    let mut reply = Vec::with_capacity(48 + response.len());
+                    for word in [RET_SUBMIT, seqnum, 0, 0, 0] {
+                        reply.extend_from_slice(&word.to_be_bytes());
+                    }
+                    reply.extend_from_slice(&status.to_be_bytes());
+                    reply.extend_from_slice(&(actual as u32).to_be_bytes());
+                    // start_frame, number_of_packets (none, not isochronous), error_count
+                    reply.extend_from_slice(&0u32.to_be_bytes());
+                    reply.extend_from_slice(&u32::MAX.to_be_bytes());
+                    reply.extend_from_slice(&0u32.to_be_bytes());
+                    reply.extend_from_slice(&[0; 8]);
+                    reply.extend_from_slice(&response);
+                    self.stream.write_all(&reply)?;
+                }
+                CMD_UNLINK => {
+                    // every URB completes right away, there is never anything left to unlink
+                    let mut reply = Vec::with_capacity(48);
+                    for word in [RET_UNLINK, seqnum, 0, 0, 0, 0] {
+                        reply.extend_from_slice(&word.to_be_bytes());
+                    }
+                    reply.extend_from_slice(&[0; 24]);
+                    self.stream.write_all(&reply)?;
+                }
+                command => return Err(invalid(format!("unknown command {command:#x}"))),
+            }
+        }
+    }
+
+    fn control(&self, setup: &Setup, data: &[u8]) -> Result<Vec<u8>, Fault> {
+        if !self.emulator.is_connected() {
+            return Err(Fault::Disconnect);
+        }
+        let mut response = match (setup.request_type, setup.request) {
+            (0x80, REQUEST_GET_DESCRIPTOR) => descriptor(setup.value)?,
+            (0x80, REQUEST_GET_STATUS) => vec![0, 0],
+            (0x80, REQUEST_GET_CONFIGURATION) => vec![1],
+            (0x00, REQUEST_SET_CONFIGURATION) | (0x01, REQUEST_SET_INTERFACE) => Vec::new(),
+            (0x43, REQUEST_ID_WRITE) if setup.index == REQUEST_INDEX => {
+                self.emulator.control_out(data)?;
+                Vec::new()
+            }
+            (0xC3, REQUEST_ID_READ) if setup.index == REQUEST_INDEX => {
+                self.emulator.control_in(setup.length)?
+            }
+            _ => {
+                log::debug!(
+                    "usbip: stalling request {:#04x}/{:#04x}",
+                    setup.request_type,
+                    setup.request
+                );
+                return Err(Fault::Stall);
+            }
+        };
+        response.truncate(setup.length as usize);
+        Ok(response)
+    }
+}
+
+/// Configuration descriptor with a single vendor specific interface, so no driver binds to it
+#[rustfmt::skip]
+const CONFIGURATION: [u8; 18] = [
+    // configuration 1, one interface, self powered, 100mA
+    9, DESCRIPTOR_CONFIGURATION, 18, 0, 1, 1, 0, 0xC0, 50,
+    // interface 0, no endpoints, vendor class
+    9, 0x04, 0, 0, 0, 0xff, 0, 0, 0,
+];
+
+fn descriptor(value: u16) -> Result<Vec<u8>, Fault> {
+    let [index, kind] = value.to_le_bytes();
+    let descriptor = match (kind, index) {
+        (DESCRIPTOR_DEVICE, _) => {
+            let mut d = vec![18, DESCRIPTOR_DEVICE, 0x00, 0x02, 0x00, 0x00, 0x00, 64];
+            d.extend_from_slice(&MOONDROP_VID.to_le_bytes());
+            d.extend_from_slice(&DAWN_PRO_PID.to_le_bytes());
+            d.extend_from_slice(&BCD_DEVICE.to_le_bytes());
+            d.extend_from_slice(&[1, 2, 3, 1]);
+            d
+        }
+        (DESCRIPTOR_CONFIGURATION, 0) => CONFIGURATION.to_vec(),
+        (DESCRIPTOR_STRING, 0) => vec![4, DESCRIPTOR_STRING, 0x09, 0x04],
+        (DESCRIPTOR_STRING, 1..=3) => {
+            let s = [MANUFACTURER, PRODUCT, SERIAL][index as usize - 1];
+            let mut d = vec![0, DESCRIPTOR_STRING];
+            d.extend(s.encode_utf16().flat_map(u16::to_le_bytes));
+            d[0] = d.len() as u8;
+            d
+        }
+        _ => return Err(Fault::Stall),
+    };
+    Ok(descriptor)
+}
+
+fn op_header(code: u16, status: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(8);
+    header.extend_from_slice(&USBIP_VERSION.to_be_bytes());
+    header.extend_from_slice(&code.to_be_bytes());
+    header.extend_from_slice(&status.to_be_bytes());
+    header
+}
+
+/// `usbip_usb_device`, without the interfaces
+fn device_info() -> Vec<u8> {
+    let mut info = Vec::with_capacity(312);
+    info.extend_from_slice(&padded::<256>(SYSFS_PATH));
+    info.extend_from_slice(&padded::<32>(BUS_ID));
+    info.extend_from_slice(&BUS_NUM.to_be_bytes());
+    info.extend_from_slice(&DEV_NUM.to_be_bytes());
+    info.extend_from_slice(&SPEED_HIGH.to_be_bytes());
+    info.extend_from_slice(&MOONDROP_VID.to_be_bytes());
+    info.extend_from_slice(&DAWN_PRO_PID.to_be_bytes());
+    info.extend_from_slice(&BCD_DEVICE.to_be_bytes());
+    // class, subclass, protocol, configuration value, configurations, interfaces
+    info.extend_from_slice(&[0, 0, 0, 1, 1, 1]);
+    info
+}
+
+fn padded<const N: usize>(s: &str) -> [u8; N] {
+    let mut buf = [0; N];
+    buf[..s.len()].copy_from_slice(s.as_bytes());
+    buf
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
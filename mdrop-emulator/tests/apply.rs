@@ -0,0 +1,96 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mdrop::device::MoondropDevice;
+use mdrop::filter::Filter;
+use mdrop::protocol;
+use mdrop::selector::DeviceSelector;
+use mdrop::settings::Settings;
+use mdrop::transport::{Backend, TransferFuture, Transport};
+use mdrop::volume::Volume;
+use mdrop::{Error, Moondrop, Result};
+use mdrop_emulator::{EmulatedTransport, Emulator, EmulatorBackend, Fault};
+
+use common::{REGISTERS, dawn_pro};
+
+/// Stalls the `n`th write of a setting, counting from 1, queries go through untouched
+#[derive(Debug)]
+struct StallingTransport {
+    emulator: Arc<Emulator>,
+    inner: EmulatedTransport,
+    writes: AtomicUsize,
+    n: usize,
+}
+
+impl Transport for StallingTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        if !protocol::is_query(data) && self.writes.fetch_add(1, Ordering::SeqCst) + 1 == self.n {
+            self.emulator.inject(Fault::Stall);
+        }
+        self.inner.control_out(data)
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        self.inner.control_in(length)
+    }
+}
+
+#[derive(Debug)]
+struct StallingBackend {
+    emulator: Arc<Emulator>,
+    inner: EmulatorBackend,
+    n: usize,
+}
+
+impl Backend for StallingBackend {
+    fn enumerate(&self) -> Result<Vec<MoondropDevice>> {
+        let devices = self.inner.enumerate()?;
+        let devices = devices
+            .into_iter()
+            .map(|device| {
+                let transport = StallingTransport {
+                    emulator: self.emulator.clone(),
+                    inner: EmulatedTransport::new(self.emulator.clone()),
+                    writes: AtomicUsize::new(0),
+                    n: self.n,
+                };
+                MoondropDevice::new(device.descriptor, Arc::new(transport))
+            })
+            .collect();
+        Ok(devices)
+    }
+}
+
+#[test]
+fn failed_apply_is_rolled_back() {
+    let (emulator, inner) = dawn_pro();
+    let backend = StallingBackend {
+        emulator: emulator.clone(),
+        inner,
+        n: 2,
+    };
+    let mut moondrop = Moondrop::with_backend(backend).unwrap();
+
+    // the quieter volume is written first, the filter second
+    let settings = Settings {
+        volume: Some(Volume::from_payload(0x50)),
+        filter: Some(Filter::SlowRollOffLowLatency),
+        ..Settings::default()
+    };
+    let result = moondrop.apply(&DeviceSelector::Any, &settings);
+    match result {
+        Err(Error::Apply {
+            setting,
+            error,
+            rollback,
+        }) => {
+            assert_eq!(setting, "filter");
+            assert!(matches!(*error, Error::Stall), "{error:?}");
+            assert!(rollback.is_none(), "{rollback:?}");
+        }
+        other => panic!("expected the apply to fail, got {other:?}"),
+    }
+    assert_eq!(emulator.registers(), REGISTERS);
+}
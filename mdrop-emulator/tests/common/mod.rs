@@ -0,0 +1,19 @@
+use std::sync::Arc;
+
+use mdrop::mock::Registers;
+use mdrop_emulator::{Emulator, EmulatorBackend};
+
+pub const REGISTERS: Registers = Registers {
+    volume: 0x40,
+    filter: 0,
+    gain: 0,
+    indicator_state: 0,
+};
+
+/// An emulated Dawn Pro starting out at [`REGISTERS`], attached to its own backend
+pub fn dawn_pro() -> (Arc<Emulator>, EmulatorBackend) {
+    let emulator = Arc::new(Emulator::new(REGISTERS));
+    let mut backend = EmulatorBackend::new();
+    backend.attach(emulator.clone());
+    (emulator, backend)
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use mdrop::mock::Registers;
+use mdrop::protocol::{self, DecodeError, RESPONSE_LEN};
+use mdrop::selector::DeviceSelector;
+use mdrop::volume::Volume;
+use mdrop::{Error, Moondrop};
+use mdrop_emulator::Fault;
+
+use common::{REGISTERS, dawn_pro};
+
+#[test]
+fn short_read_is_a_decode_error() {
+    let (emulator, backend) = dawn_pro();
+    let moondrop = Moondrop::with_backend(backend).unwrap();
+    emulator.inject(Fault::ShortRead(3));
+
+    match moondrop.get_volume(&DeviceSelector::Any) {
+        Err(Error::Decode {
+            reason: DecodeError::Length { expected, actual },
+            data,
+        }) => {
+            assert_eq!((expected, actual), (RESPONSE_LEN, 3));
+            assert_eq!(data, protocol::GET_VOLUME);
+        }
+        other => panic!("expected a decode error, got {other:?}"),
+    }
+    // the next read isn't affected
+    assert_eq!(
+        moondrop.get_volume(&DeviceSelector::Any).unwrap(),
+        Volume::from_payload(REGISTERS.volume)
+    );
+}
+
+#[test]
+fn unknown_register_value_is_a_decode_error() {
+    let (emulator, backend) = dawn_pro();
+    let moondrop = Moondrop::with_backend(backend).unwrap();
+    emulator.set_registers(Registers {
+        filter: 0x42,
+        ..REGISTERS
+    });
+
+    let result = moondrop.get_filter(&DeviceSelector::Any);
+    assert!(
+        matches!(
+            result,
+            Err(Error::Decode {
+                reason: DecodeError::UnknownValue {
+                    field: "filter",
+                    value: 0x42
+                },
+                ..
+            })
+        ),
+        "{result:?}"
+    );
+}
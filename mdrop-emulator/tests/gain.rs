@@ -0,0 +1,206 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use mdrop::auto_gain::AutoGain;
+use mdrop::device::MoondropDevice;
+use mdrop::gain::Gain;
+use mdrop::mock::Registers;
+use mdrop::protocol::{self, SET_GAIN, SET_VOLUME};
+use mdrop::selector::DeviceSelector;
+use mdrop::transport::{Backend, TransferFuture, Transport};
+use mdrop::volume::{Volume, VolumeDelta};
+use mdrop::{Error, Moondrop};
+use mdrop_emulator::{EmulatedTransport, Emulator, EmulatorBackend};
+
+use common::{REGISTERS, dawn_pro};
+
+const OFFSET_DB: f32 = 6.0;
+
+/// Commands written to the dongle, oldest first
+type Writes = Arc<Mutex<Vec<Vec<u8>>>>;
+
+/// Records the commands changing a setting, queries go through untouched
+#[derive(Debug)]
+struct RecordingTransport {
+    inner: EmulatedTransport,
+    writes: Writes,
+}
+
+impl Transport for RecordingTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        if !protocol::is_query(data) {
+            self.writes.lock().unwrap().push(data.to_vec());
+        }
+        self.inner.control_out(data)
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        self.inner.control_in(length)
+    }
+}
+
+#[derive(Debug)]
+struct RecordingBackend {
+    emulator: Arc<Emulator>,
+    inner: EmulatorBackend,
+    writes: Writes,
+}
+
+impl Backend for RecordingBackend {
+    fn enumerate(&self) -> mdrop::Result<Vec<MoondropDevice>> {
+        let devices = self.inner.enumerate()?;
+        let devices = devices
+            .into_iter()
+            .map(|device| {
+                let transport = RecordingTransport {
+                    inner: EmulatedTransport::new(self.emulator.clone()),
+                    writes: self.writes.clone(),
+                };
+                MoondropDevice::new(device.descriptor, Arc::new(transport))
+            })
+            .collect();
+        Ok(devices)
+    }
+}
+
+/// A Dawn Pro keeping its loudness across gain changes, and the commands written to it
+fn keeping_loudness() -> (Arc<Emulator>, Writes, Moondrop) {
+    let (emulator, inner) = dawn_pro();
+    let writes = Writes::default();
+    let backend = RecordingBackend {
+        emulator: emulator.clone(),
+        inner,
+        writes: Arc::clone(&writes),
+    };
+    let mut moondrop = Moondrop::with_backend(backend).unwrap();
+    moondrop.set_keep_loudness(true);
+    (emulator, writes, moondrop)
+}
+
+fn command(cmd: [u8; 3], value: u8) -> Vec<u8> {
+    let mut data = cmd.to_vec();
+    data.push(value);
+    data
+}
+
+#[test]
+fn keep_loudness_compensates_the_volume() {
+    let (emulator, writes, mut moondrop) = keeping_loudness();
+    moondrop.set_gain_offset(Some(OFFSET_DB));
+    emulator.set_registers(Registers {
+        volume: 0x20,
+        ..REGISTERS
+    });
+
+    // 6 dB quieter is 12 steps of 0.5 dB, lowered before the gain goes up
+    let info = moondrop.set_gain(&DeviceSelector::Any, Gain::High).unwrap();
+    assert_eq!(
+        (info.gain, info.volume),
+        (Gain::High, Volume::from_payload(0x2c))
+    );
+    assert_eq!(
+        writes.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        [command(SET_VOLUME, 0x2c), command(SET_GAIN, 1)]
+    );
+
+    // and back, the gain goes down before the volume goes up
+    let info = moondrop.set_gain(&DeviceSelector::Any, Gain::Low).unwrap();
+    assert_eq!(
+        (info.gain, info.volume),
+        (Gain::Low, Volume::from_payload(0x20))
+    );
+    assert_eq!(
+        writes.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        [command(SET_GAIN, 0), command(SET_VOLUME, 0x20)]
+    );
+    assert_eq!(
+        emulator.registers(),
+        Registers {
+            volume: 0x20,
+            ..REGISTERS
+        }
+    );
+}
+
+#[test]
+fn keep_loudness_needs_the_gain_offset() {
+    let (emulator, writes, mut moondrop) = keeping_loudness();
+
+    let result = moondrop.set_gain(&DeviceSelector::Any, Gain::High);
+    assert!(
+        matches!(result, Err(Error::UnsupportedSetting { .. })),
+        "{result:?}"
+    );
+    assert!(writes.lock().unwrap().is_empty());
+    assert_eq!(emulator.registers(), REGISTERS);
+}
+
+/// A Dawn Pro at -16 dB and Low gain, with auto gain picking its gain
+fn auto_gain() -> (Arc<Emulator>, Writes, Moondrop) {
+    let (emulator, writes, mut moondrop) = keeping_loudness();
+    moondrop.set_keep_loudness(false);
+    moondrop.set_gain_offset(Some(OFFSET_DB));
+    moondrop.set_auto_gain(Some(AutoGain::default())).unwrap();
+    emulator.set_registers(Registers {
+        volume: 0x20,
+        ..REGISTERS
+    });
+    (emulator, writes, moondrop)
+}
+
+#[test]
+fn auto_gain_reaches_past_the_loudest_volume_at_low_gain() {
+    let (emulator, writes, mut moondrop) = auto_gain();
+
+    // +3 dB is -3 dB at High gain, 6 steps down from the top
+    let info = moondrop.set_loudness(&DeviceSelector::Any, 3.0).unwrap();
+    assert_eq!(
+        (info.gain, info.volume),
+        (Gain::High, Volume::from_payload(6))
+    );
+    assert_eq!(
+        emulator.registers(),
+        Registers {
+            volume: 6,
+            gain: 1,
+            ..REGISTERS
+        }
+    );
+    // the volume is already quieter than before, so the gain goes up first
+    assert_eq!(
+        writes.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        [command(SET_GAIN, 1), command(SET_VOLUME, 6)]
+    );
+
+    // louder than the gain offset allows stops at the loudest volume
+    let info = moondrop
+        .adjust_volume(&DeviceSelector::Any, VolumeDelta::Db(10.0))
+        .unwrap();
+    assert_eq!((info.gain, info.volume), (Gain::High, Volume::MAX));
+}
+
+#[test]
+fn auto_gain_needs_the_gain_offset() {
+    let (emulator, writes, mut moondrop) = keeping_loudness();
+    moondrop.set_keep_loudness(false);
+
+    let result = moondrop.set_auto_gain(Some(AutoGain::default()));
+    assert!(
+        matches!(result, Err(Error::UnsupportedSetting { .. })),
+        "{result:?}"
+    );
+    // nothing changed, volumes are written as they are
+    let info = moondrop
+        .set_volume(&DeviceSelector::Any, Volume::from_payload(0x10))
+        .unwrap();
+    assert_eq!(
+        (info.gain, info.volume),
+        (Gain::Low, Volume::from_payload(0x10))
+    );
+    assert_eq!(
+        writes.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        [command(SET_VOLUME, 0x10)]
+    );
+    assert_eq!(emulator.registers().gain, 0);
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mdrop::Moondrop;
+use mdrop::device::MoondropDevice;
+use mdrop::mock::Registers;
+use mdrop::monitor::SettingChange;
+use mdrop::protocol::GET_ANY;
+use mdrop::selector::DeviceSelector;
+use mdrop::transport::{Backend, TransferFuture, Transport};
+use mdrop::volume::Volume;
+use mdrop_emulator::{EmulatedTransport, Emulator, EmulatorBackend};
+
+use common::{REGISTERS, dawn_pro};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counts how often a dongle is read in full
+#[derive(Debug)]
+struct CountingTransport {
+    inner: EmulatedTransport,
+    reads: Arc<AtomicUsize>,
+}
+
+impl Transport for CountingTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        if data == GET_ANY {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+        }
+        self.inner.control_out(data)
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        self.inner.control_in(length)
+    }
+}
+
+/// Counts how often the bus gets listed and the dongles read
+#[derive(Debug)]
+struct CountingBackend {
+    emulator: Arc<Emulator>,
+    inner: EmulatorBackend,
+    enumerations: Arc<AtomicUsize>,
+    reads: Arc<AtomicUsize>,
+}
+
+impl Backend for CountingBackend {
+    fn enumerate(&self) -> mdrop::Result<Vec<MoondropDevice>> {
+        self.enumerations.fetch_add(1, Ordering::SeqCst);
+        let devices = self.inner.enumerate()?;
+        let devices = devices
+            .into_iter()
+            .map(|device| {
+                let transport = CountingTransport {
+                    inner: EmulatedTransport::new(self.emulator.clone()),
+                    reads: self.reads.clone(),
+                };
+                MoondropDevice::new(device.descriptor, Arc::new(transport))
+            })
+            .collect();
+        Ok(devices)
+    }
+}
+
+/// Waits for `counter` to go past `n`, a full read of the dongle having completed by then
+fn wait_for(counter: &AtomicUsize, n: usize) {
+    let deadline = Instant::now() + TIMEOUT;
+    while counter.load(Ordering::SeqCst) <= n {
+        assert!(Instant::now() < deadline, "the monitor stopped polling");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn monitor_reports_changes_without_listing_the_bus_again() {
+    let (emulator, inner) = dawn_pro();
+    let enumerations = Arc::new(AtomicUsize::new(0));
+    let reads = Arc::new(AtomicUsize::new(0));
+    let backend = CountingBackend {
+        emulator: emulator.clone(),
+        inner,
+        enumerations: enumerations.clone(),
+        reads: reads.clone(),
+    };
+    let moondrop = Moondrop::with_backend(backend).unwrap();
+    let monitor = moondrop.monitor(&DeviceSelector::Any, Duration::from_millis(5));
+    let handle = monitor.handle();
+    let (tx, events) = mpsc::channel();
+    let listener = thread::spawn(move || {
+        for event in monitor {
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    // the second read starting means the first one, the baseline, is done
+    wait_for(&reads, 1);
+    let listed = enumerations.load(Ordering::SeqCst);
+    emulator.set_registers(Registers {
+        volume: 0x30,
+        ..REGISTERS
+    });
+    let event = events.recv_timeout(TIMEOUT).unwrap_or_else(|e| {
+        handle.stop();
+        panic!("no change reported: {e}")
+    });
+    assert_eq!(event.old.volume, Volume::from_payload(REGISTERS.volume));
+    assert_eq!(event.new.volume, Volume::from_payload(0x30));
+    assert_eq!(
+        event.changes(),
+        [SettingChange::Volume {
+            old: Volume::from_payload(REGISTERS.volume),
+            new: Volume::from_payload(0x30),
+        }]
+    );
+
+    // later reads of the same dongle don't list the bus either
+    wait_for(&reads, reads.load(Ordering::SeqCst) + 1);
+    handle.stop();
+    listener.join().unwrap();
+    assert_eq!(enumerations.load(Ordering::SeqCst), listed);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mdrop::Moondrop;
+use mdrop::device::MoondropDevice;
+use mdrop::mock::Registers;
+use mdrop::protocol::{self, SET_VOLUME};
+use mdrop::ramp::Cancel;
+use mdrop::selector::DeviceSelector;
+use mdrop::transport::{Backend, TransferFuture, Transport};
+use mdrop::volume::Volume;
+use mdrop_emulator::{EmulatedTransport, Emulator, EmulatorBackend};
+
+use common::{REGISTERS, dawn_pro};
+
+/// Volumes written to the dongle, oldest first
+type Writes = Arc<Mutex<Vec<u8>>>;
+
+/// Records the volumes written, and cancels the ramp once `cancel_after` of them went through
+#[derive(Debug)]
+struct CancellingTransport {
+    inner: EmulatedTransport,
+    writes: Writes,
+    cancel: Cancel,
+    cancel_after: usize,
+}
+
+impl Transport for CancellingTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        if !protocol::is_query(data) && data[..3] == SET_VOLUME {
+            let mut writes = self.writes.lock().unwrap();
+            writes.push(data[3]);
+            if writes.len() == self.cancel_after {
+                self.cancel.cancel();
+            }
+        }
+        self.inner.control_out(data)
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        self.inner.control_in(length)
+    }
+}
+
+#[derive(Debug)]
+struct CancellingBackend {
+    emulator: Arc<Emulator>,
+    inner: EmulatorBackend,
+    writes: Writes,
+    cancel: Cancel,
+    cancel_after: usize,
+}
+
+impl Backend for CancellingBackend {
+    fn enumerate(&self) -> mdrop::Result<Vec<MoondropDevice>> {
+        let devices = self.inner.enumerate()?;
+        let devices = devices
+            .into_iter()
+            .map(|device| {
+                let transport = CancellingTransport {
+                    inner: EmulatedTransport::new(self.emulator.clone()),
+                    writes: self.writes.clone(),
+                    cancel: self.cancel.clone(),
+                    cancel_after: self.cancel_after,
+                };
+                MoondropDevice::new(device.descriptor, Arc::new(transport))
+            })
+            .collect();
+        Ok(devices)
+    }
+}
+
+/// Ramps the Dawn Pro from [`REGISTERS`] to `target`, cancelling after `cancel_after` volume
+/// writes, and returns the volumes written and the one the ramp reported
+fn ramp(target: u8, cancel_after: usize) -> (Arc<Emulator>, Vec<u8>, Volume) {
+    let (emulator, inner) = dawn_pro();
+    let writes = Writes::default();
+    let cancel = Cancel::new();
+    let backend = CancellingBackend {
+        emulator: emulator.clone(),
+        inner,
+        writes: Arc::clone(&writes),
+        cancel: cancel.clone(),
+        cancel_after,
+    };
+    let mut moondrop = Moondrop::with_backend(backend).unwrap();
+    let info = moondrop
+        .ramp_volume(
+            &DeviceSelector::Any,
+            Volume::from_payload(target),
+            Duration::ZERO,
+            &cancel,
+        )
+        .unwrap();
+    let writes = writes.lock().unwrap().clone();
+    (emulator, writes, info.volume)
+}
+
+#[test]
+fn walks_every_step_to_the_target() {
+    let (emulator, writes, volume) = ramp(0x3c, usize::MAX);
+    assert_eq!(writes, [0x3f, 0x3e, 0x3d, 0x3c]);
+    assert_eq!(volume, Volume::from_payload(0x3c));
+    assert_eq!(
+        emulator.registers(),
+        Registers {
+            volume: 0x3c,
+            ..REGISTERS
+        }
+    );
+}
+
+#[test]
+fn cancelling_stops_before_the_next_write() {
+    for cancel_after in 1..=4 {
+        let (emulator, writes, volume) = ramp(0x44, cancel_after);
+        let expected = &[0x41, 0x42, 0x43, 0x44][..cancel_after];
+        assert_eq!(writes, expected, "cancelled after {cancel_after} writes");
+        // the ramp reports where it got to
+        let reached = *expected.last().unwrap();
+        assert_eq!(volume, Volume::from_payload(reached));
+        assert_eq!(emulator.registers().volume, reached);
+    }
+}
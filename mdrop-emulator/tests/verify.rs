@@ -0,0 +1,47 @@
+mod common;
+
+use mdrop::filter::Filter;
+use mdrop::selector::DeviceSelector;
+use mdrop::setting::Setting;
+use mdrop::{Error, Moondrop};
+use mdrop_emulator::Fault;
+
+use common::{REGISTERS, dawn_pro};
+
+#[test]
+fn dropped_write_is_retried() {
+    let (emulator, backend) = dawn_pro();
+    let mut moondrop = Moondrop::with_backend(backend).unwrap();
+    emulator.inject(Fault::DropWrite);
+    emulator.inject(Fault::DropWrite);
+
+    let info = moondrop
+        .set_filter(&DeviceSelector::Any, Filter::SlowRollOffLowLatency)
+        .unwrap();
+    assert_eq!(info.filter, Filter::SlowRollOffLowLatency);
+    assert_eq!(
+        emulator.registers().filter,
+        Filter::SlowRollOffLowLatency.to_u8()
+    );
+}
+
+#[test]
+fn dropped_write_without_retries_is_a_mismatch() {
+    let (emulator, backend) = dawn_pro();
+    let mut moondrop = Moondrop::with_backend(backend).unwrap();
+    moondrop.set_verify_retries(0);
+    emulator.inject(Fault::DropWrite);
+
+    let result = moondrop.set_filter(&DeviceSelector::Any, Filter::SlowRollOffLowLatency);
+    assert!(
+        matches!(
+            result,
+            Err(Error::Mismatch {
+                setting: "filter",
+                ..
+            })
+        ),
+        "{result:?}"
+    );
+    assert_eq!(emulator.registers(), REGISTERS);
+}
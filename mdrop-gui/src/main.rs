@@ -1,14 +1,25 @@
+use std::time::Duration;
+
+use futures_lite::future;
 use iced::futures::channel::mpsc;
-use iced::futures::{SinkExt, Stream};
+use iced::futures::{SinkExt, Stream, StreamExt};
 use iced::widget::{column, container, pick_list, slider, svg, text};
-use iced::{Center, Element, Fill, Size, Subscription, Theme, stream};
+use iced::{Center, Element, Fill, Size, Subscription, Task, Theme, stream};
 use mdrop::filter::Filter;
 use mdrop::gain::Gain;
 use mdrop::indicator_state::IndicatorState;
+use mdrop::monitor::{DEFAULT_POLL_INTERVAL, MonitorEvent};
+use mdrop::ramp::Cancel;
+use mdrop::selector::DeviceSelector;
+use mdrop::setting::Setting;
 use mdrop::volume::Volume;
-use mdrop::{Moondrop, MoondropInfo};
+use mdrop::watch::{DeviceEvent, WatchHandle};
+use mdrop::{AsyncMoondrop, MoondropInfo};
 
 const WIDTH: u32 = 300;
+/// Slider jumps of more than this many percent are ramped instead of set at once
+const RAMP_THRESHOLD: u32 = 10;
+const RAMP_DURATION: Duration = Duration::from_millis(500);
 
 pub fn main() -> iced::Result {
     env_logger::init();
@@ -31,76 +42,202 @@ pub enum Message {
     SelectFilter(Filter),
     SelectIndicator(IndicatorState),
     SelectGain(Gain),
-    UpdateDevice(Option<MoondropInfo>),
+    /// The worker owning the dongle session is up and takes writes over this channel
+    WorkerReady(mpsc::UnboundedSender<Request>),
+    Device(DeviceEvent),
+    Polled(MonitorEvent),
+    /// Outcome of the write of the given generation
+    Written(u64, Result<MoondropInfo, String>),
 }
 
+impl Message {
+    fn written(generation: u64, result: mdrop::Result<MoondropInfo>) -> Self {
+        Message::Written(generation, result.map_err(|e| e.to_string()))
+    }
+}
+
+/// A write for the worker to make on the dongle session
+#[derive(Debug)]
+pub struct Request {
+    generation: u64,
+    selector: DeviceSelector,
+    write: Write,
+}
+
+#[derive(Debug)]
+enum Write {
+    Volume(Volume),
+    Ramp(Volume, Cancel),
+    Filter(Filter),
+    Gain(Gain),
+    IndicatorState(IndicatorState),
+}
+
+impl Request {
+    async fn run(self, moondrop: &mut AsyncMoondrop) -> mdrop::Result<MoondropInfo> {
+        let selector = &self.selector;
+        match self.write {
+            Write::Volume(volume) => moondrop.set_volume(selector, volume).await,
+            Write::Ramp(volume, cancel) => {
+                moondrop
+                    .ramp_volume(selector, volume, RAMP_DURATION, &cancel)
+                    .await
+            }
+            Write::Filter(filter) => moondrop.set_filter(selector, filter).await,
+            Write::Gain(gain) => moondrop.set_gain(selector, gain).await,
+            Write::IndicatorState(indicator_state) => {
+                moondrop
+                    .set_indicator_state(selector, indicator_state)
+                    .await
+            }
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct MdropGui {
-    moondrop: Moondrop,
+    /// `None` until the worker is up, or when the USB devices couldn't be listed
+    worker: Option<mpsc::UnboundedSender<Request>>,
     info: Option<MoondropInfo>,
+    /// USB port path of the dongle shown, it stays the same when the dongle re-enumerates
+    port: Option<String>,
+    /// Volume the dongle last reported, the slider moves ahead of it
+    volume: Option<Volume>,
+    ramp: Cancel,
+    /// Bumped with every write, the outcomes of older ones are out of date by the time they arrive
+    generation: u64,
 }
 
 impl MdropGui {
-    fn update(&mut self, message: Message) {
+    fn selector(info: &MoondropInfo) -> DeviceSelector {
+        DeviceSelector::BusAddress(info.bus)
+    }
+
+    /// Hands `write` to the worker, for the dongle shown
+    fn send(&mut self, write: Write) {
+        let (Some(info), Some(worker)) = (self.info.as_ref(), self.worker.as_ref()) else {
+            return;
+        };
+        self.generation += 1;
+        let request = Request {
+            generation: self.generation,
+            selector: Self::selector(info),
+            write,
+        };
+        if worker.unbounded_send(request).is_err() {
+            log::error!("failed to write setting: the worker stopped");
+        }
+    }
+
+    fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::SetVolume => {
-                if let Some(info) = self.info.as_ref() {
-                    self.moondrop.set_volume(info.volume);
+                if let Some(volume) = self.info.as_ref().map(|info| info.volume) {
+                    // a newer release takes over from a ramp still running
+                    self.ramp.cancel();
+                    self.ramp = Cancel::new();
+                    let jump = self
+                        .volume
+                        .map_or(0, |current| current.percent().abs_diff(volume.percent()));
+                    let write = match jump <= RAMP_THRESHOLD {
+                        true => Write::Volume(volume),
+                        false => Write::Ramp(volume, self.ramp.clone()),
+                    };
+                    self.send(write);
                 }
             }
             Message::VolumeChanged(value) => {
                 if let Some(info) = self.info.as_mut() {
-                    info.volume = Volume::new(value);
+                    info.volume = Volume::from_percent(value);
                 }
             }
             Message::SelectFilter(filter) => {
                 if let Some(info) = self.info.as_mut() {
                     info.filter = filter;
-                    self.moondrop.set_filter(filter);
+                    self.send(Write::Filter(filter));
                 }
             }
             Message::SelectIndicator(indicator_state) => {
                 if let Some(info) = self.info.as_mut() {
                     info.indicator_state = indicator_state;
-                    self.moondrop.set_indicator_state(indicator_state);
+                    self.send(Write::IndicatorState(indicator_state));
                 }
             }
             Message::SelectGain(gain) => {
                 if let Some(info) = self.info.as_mut() {
                     info.gain = gain;
-                    self.moondrop.set_gain(gain);
+                    self.send(Write::Gain(gain));
+                }
+            }
+            Message::WorkerReady(worker) => self.worker = Some(worker),
+            Message::Device(event) => {
+                log::debug!("device event: {event:?}");
+                let port = event.device().port_path();
+                // the first dongle attached is shown until it goes away
+                if self.port.as_ref().is_none_or(|shown| *shown == port) {
+                    match event {
+                        DeviceEvent::Added {
+                            info: Some(info), ..
+                        }
+                        | DeviceEvent::StateChanged { info, .. } => {
+                            self.port = Some(port);
+                            self.volume = Some(info.volume);
+                            self.info = Some(info);
+                        }
+                        DeviceEvent::Removed { .. } => {
+                            self.port = None;
+                            self.volume = None;
+                            self.info = None;
+                        }
+                        DeviceEvent::Added { info: None, .. } => {}
+                    }
                 }
             }
-            Message::UpdateDevice(moondrop_info) => {
-                log::debug!("app update: {:?}", moondrop_info);
-                self.info = moondrop_info;
+            Message::Polled(event) => {
+                // changed on the dongle itself, ex. with its volume buttons
+                if self.port.as_ref() == Some(&event.device.port_path()) {
+                    log::debug!("dongle changed: {:?}", event.changes());
+                    self.volume = Some(event.new.volume);
+                    self.info = Some(event.new);
+                }
             }
+            // a newer write is on its way, ex. a cancelled ramp reporting where it stopped
+            Message::Written(generation, Ok(_)) if generation != self.generation => {}
+            Message::Written(_, result) => match result {
+                // show what the dongle confirmed rather than what was asked for
+                Ok(info) => {
+                    self.volume = Some(info.volume);
+                    self.info = Some(info);
+                }
+                Err(e) => log::error!("failed to write setting: {e}"),
+            },
         }
+        Task::none()
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         match &self.info {
             Some(info) => {
                 let name = text(&info.name);
 
                 let filter_list =
-                    pick_list(&Filter::ALL[..], Some(info.filter), Message::SelectFilter)
-                        .width(WIDTH);
+                    pick_list(Filter::ALL, Some(info.filter), Message::SelectFilter).width(WIDTH);
                 let gain_list =
-                    pick_list(&Gain::ALL[..], Some(info.gain), Message::SelectGain).width(WIDTH);
+                    pick_list(Gain::ALL, Some(info.gain), Message::SelectGain).width(WIDTH);
                 let indicator_list = pick_list(
-                    &IndicatorState::ALL[..],
+                    IndicatorState::ALL,
                     Some(info.indicator_state),
                     Message::SelectIndicator,
                 )
                 .width(WIDTH);
                 let h_slider = container(
-                    slider(1..=100, info.volume.inner(), Message::VolumeChanged)
+                    slider(1..=100, info.volume.percent(), Message::VolumeChanged)
                         .on_release(Message::SetVolume)
                         .shift_step(5u32),
                 )
                 .width(WIDTH);
 
-                let text = text(info.volume.inner());
+                let text = text(info.volume.percent());
 
                 column![name, gain_list, indicator_list, filter_list, h_slider, text,]
                     .width(Fill)
@@ -128,7 +265,7 @@ impl MdropGui {
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        Subscription::run(worker).map(Message::UpdateDevice)
+        Subscription::run(worker)
     }
 
     fn theme(&self) -> Theme {
@@ -136,30 +273,69 @@ impl MdropGui {
     }
 }
 
-fn worker() -> impl Stream<Item = Option<MoondropInfo>> {
-    stream::channel(
-        1,
-        async move |mut output: mpsc::Sender<Option<MoondropInfo>>| {
-            let mut moondrop = Moondrop::new();
-            let (tx, rx) = std::sync::mpsc::channel();
-
-            std::thread::spawn(move || {
-                moondrop.watch(tx);
-            });
-
-            loop {
-                let data = rx.recv().unwrap();
-                output.send(None).await.expect("dummy send");
-                output.send(data).await.expect("failed to send data");
+/// Owns the one session to the dongles: the watcher, the monitor and the writes asked for by the
+/// GUI all go through it
+fn worker() -> impl Stream<Item = Message> {
+    stream::channel(1, async move |mut output: mpsc::Sender<Message>| {
+        let mut moondrop = match AsyncMoondrop::new() {
+            Ok(moondrop) => moondrop,
+            Err(e) => {
+                log::error!("failed to list USB devices: {e}");
+                return;
+            }
+        };
+        let watch = match moondrop.watch() {
+            Ok(watch) => watch,
+            Err(e) => {
+                log::error!("failed to watch devices: {e}");
+                return;
+            }
+        };
+        let monitor = moondrop.monitor(&DeviceSelector::Any, DEFAULT_POLL_INTERVAL);
+        // this future lives as long as the subscription, dropping it stops both right away rather
+        // than once their threads fail to send the next event
+        let _stop = [StopOnDrop(watch.handle()), StopOnDrop(monitor.handle())];
+        forward(watch.map(Message::Device), output.clone());
+        forward(monitor.map(Message::Polled), output.clone());
+
+        let (requests_tx, mut requests) = mpsc::unbounded();
+        if output
+            .send(Message::WorkerReady(requests_tx))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        // one write at a time, in the order they were asked for
+        while let Some(request) = requests.next().await {
+            let generation = request.generation;
+            let written = Message::written(generation, request.run(&mut moondrop).await);
+            if output.send(written).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Sends the events of a blocking watcher or monitor to the GUI, from a thread of its own
+fn forward(
+    events: impl Iterator<Item = Message> + Send + 'static,
+    mut output: mpsc::Sender<Message>,
+) {
+    std::thread::spawn(move || {
+        for event in events {
+            if future::block_on(output.send(event)).is_err() {
+                break;
             }
-        },
-    )
+        }
+    });
 }
 
-impl Default for MdropGui {
-    fn default() -> Self {
-        let moondrop = Moondrop::new();
-        let info = moondrop.get_all();
-        Self { moondrop, info }
+/// Stops a watcher or monitor when dropped along with the subscription reading it
+struct StopOnDrop(WatchHandle);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.stop();
     }
 }
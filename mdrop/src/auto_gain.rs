@@ -0,0 +1,156 @@
+use crate::gain::Gain;
+use crate::volume::Volume;
+
+/// Picks the gain from the requested loudness, see [`crate::AsyncMoondrop::set_auto_gain`].
+///
+/// Requests are loudness in dB relative to the loudest volume at [`Gain::Low`], whatever gain the
+/// dongle is on, so the same request gives the same loudness every time. Above 0 dB only
+/// [`Gain::High`] gets there, up to the model's gain offset. Requests louder than `threshold` are
+/// played on [`Gain::High`] with the volume lowered by the gain offset. The way back to
+/// [`Gain::Low`] only happens once the request is `hysteresis_db` below the threshold, so hovering
+/// around it doesn't flip the gain back and forth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoGain {
+    pub threshold: Volume,
+    pub hysteresis_db: f32,
+}
+
+impl AutoGain {
+    pub fn new(threshold: Volume, hysteresis_db: f32) -> Self {
+        Self {
+            threshold,
+            hysteresis_db: hysteresis_db.abs(),
+        }
+    }
+
+    /// Gain and volume to write for `loudness_db` on a dongle currently at `gain`, `offset_db`
+    /// being how much louder [`Gain::High`] is
+    pub(crate) fn stage(&self, gain: Gain, loudness_db: f32, offset_db: f32) -> (Gain, Volume) {
+        let threshold = self.threshold.to_db();
+        let staged = match gain {
+            Gain::Low if loudness_db > threshold => Gain::High,
+            Gain::High if loudness_db >= threshold - self.hysteresis_db => Gain::High,
+            _ => Gain::Low,
+        };
+        (staged, Self::volume_at(staged, loudness_db, offset_db))
+    }
+
+    /// Volume giving `loudness_db` at `gain`, clamped to what the dongle covers
+    pub(crate) fn volume_at(gain: Gain, loudness_db: f32, offset_db: f32) -> Volume {
+        match gain {
+            Gain::Low => Volume::from_db(loudness_db),
+            Gain::High => Volume::from_db(loudness_db - offset_db),
+        }
+    }
+
+    /// Loudness of `volume` played at `gain`, the way [`AutoGain::stage`] takes it
+    pub fn loudness_db(gain: Gain, volume: Volume, offset_db: f32) -> f32 {
+        match gain {
+            Gain::Low => volume.to_db(),
+            Gain::High => volume.to_db() + offset_db,
+        }
+    }
+}
+
+impl Default for AutoGain {
+    /// Switches to High above -10 dB and back below -16 dB
+    fn default() -> Self {
+        Self::new(Volume::from_db(-10.0), 6.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFSET_DB: f32 = 6.0;
+
+    fn db(db: f32) -> Volume {
+        Volume::from_db(db)
+    }
+
+    #[test]
+    fn stays_low_up_to_the_threshold() {
+        let auto_gain = AutoGain::default();
+        assert_eq!(
+            auto_gain.stage(Gain::Low, -20.0, OFFSET_DB),
+            (Gain::Low, db(-20.0))
+        );
+        assert_eq!(
+            auto_gain.stage(Gain::Low, -10.0, OFFSET_DB),
+            (Gain::Low, db(-10.0))
+        );
+    }
+
+    #[test]
+    fn switches_to_high_over_the_threshold() {
+        let auto_gain = AutoGain::default();
+        assert_eq!(
+            auto_gain.stage(Gain::Low, -9.5, OFFSET_DB),
+            (Gain::High, db(-15.5))
+        );
+        assert_eq!(
+            auto_gain.stage(Gain::Low, -3.0, OFFSET_DB),
+            (Gain::High, db(-9.0))
+        );
+    }
+
+    #[test]
+    fn reaches_the_headroom_of_high_gain() {
+        let auto_gain = AutoGain::default();
+        assert_eq!(
+            auto_gain.stage(Gain::Low, 3.0, OFFSET_DB),
+            (Gain::High, db(-3.0))
+        );
+        assert_eq!(
+            auto_gain.stage(Gain::High, OFFSET_DB, OFFSET_DB),
+            (Gain::High, Volume::MAX)
+        );
+        assert_eq!(
+            AutoGain::loudness_db(Gain::High, Volume::MAX, OFFSET_DB),
+            OFFSET_DB
+        );
+    }
+
+    #[test]
+    fn repeating_a_request_gives_the_same_loudness() {
+        let auto_gain = AutoGain::default();
+        let (gain, volume) = auto_gain.stage(Gain::Low, -3.0, OFFSET_DB);
+        assert_eq!(auto_gain.stage(gain, -3.0, OFFSET_DB), (gain, volume));
+        // every half dB from the quietest volume at Low gain to the loudest at High
+        let quietest = Volume::MIN.to_db();
+        let steps = ((OFFSET_DB - quietest) / 0.5) as u32;
+        for step in 0..=steps {
+            let requested = quietest + step as f32 * 0.5;
+            for gain in [Gain::Low, Gain::High] {
+                let (staged, volume) = auto_gain.stage(gain, requested, OFFSET_DB);
+                assert_eq!(
+                    auto_gain.stage(staged, requested, OFFSET_DB),
+                    (staged, volume)
+                );
+                assert_eq!(
+                    AutoGain::loudness_db(staged, volume, OFFSET_DB),
+                    requested,
+                    "{requested} dB staged at {staged} gain"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn goes_back_to_low_past_the_hysteresis() {
+        let auto_gain = AutoGain::default();
+        assert_eq!(
+            auto_gain.stage(Gain::High, -12.0, OFFSET_DB),
+            (Gain::High, db(-18.0))
+        );
+        assert_eq!(
+            auto_gain.stage(Gain::High, -16.0, OFFSET_DB),
+            (Gain::High, db(-22.0))
+        );
+        assert_eq!(
+            auto_gain.stage(Gain::High, -16.5, OFFSET_DB),
+            (Gain::Low, db(-16.5))
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::device::DeviceDescriptor;
+use crate::error::Error;
+use crate::gain::Gain;
+use crate::volume::Volume;
+
+/// Environment variable overriding where the volume caps are read from
+pub const CAPS_ENV: &str = "MDROP_CAPS";
+
+/// Port given to caps covering every dongle
+pub const ANY_PORT: &str = "*";
+
+/// What happens to a volume over its cap
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum CapMode {
+    /// Lower it to the cap
+    #[default]
+    Clamp,
+    /// Refuse the change
+    Fail,
+}
+
+impl FromStr for CapMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "clamp" => Ok(CapMode::Clamp),
+            "fail" => Ok(CapMode::Fail),
+            _ => Err(Error::InvalidValue {
+                setting: "cap mode",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for CapMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CapMode::Clamp => write!(f, "clamp"),
+            CapMode::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// Loudest volume allowed on the dongle at `port` (or any, [`ANY_PORT`]) when set to `gain`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeCap {
+    pub port: String,
+    pub gain: Gain,
+    pub volume: Volume,
+}
+
+/// Per dongle and gain volume ceilings.
+///
+/// Kept in a line based file, by default `$XDG_CONFIG_HOME/mdrop/caps` (or
+/// `~/.config/mdrop/caps`):
+///
+/// ```text
+/// # lower volumes over their cap, or fail
+/// mode clamp
+/// # <port path or *> <gain> <volume in % or dB>
+/// * high -30.0dB
+/// 3-1.2 low 80%
+/// ```
+///
+/// A cap for a dongle's port takes precedence over one for `*`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VolumeCaps {
+    pub mode: CapMode,
+    caps: Vec<VolumeCap>,
+}
+
+impl VolumeCaps {
+    /// Where the caps are kept, [`CAPS_ENV`] if set, else in the user's config directory
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os(CAPS_ENV) {
+            return Some(PathBuf::from(path));
+        }
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config.join("mdrop").join("caps"))
+    }
+
+    /// Caps in [`VolumeCaps::default_path`], none if there's no such file
+    pub fn from_env() -> io::Result<Self> {
+        match Self::default_path() {
+            Some(path) => Self::load(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Reads the caps from `path`, a missing file has no caps
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                Self::parse(&contents).map_err(|e| invalid(format!("{}:{e}", path.display())))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut caps = Self::default();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let cap = match fields[..] {
+                ["mode", mode] => mode
+                    .parse()
+                    .map(|mode| caps.mode = mode)
+                    .map_err(|e: Error| e.to_string()),
+                [port, gain, volume] => {
+                    gain.parse::<Gain>()
+                        .map_err(|e| e.to_string())
+                        .and_then(|gain| {
+                            let volume = volume.parse::<Volume>().map_err(|e| e.to_string())?;
+                            caps.set(port, gain, volume);
+                            Ok(())
+                        })
+                }
+                _ => Err("expected `mode <clamp|fail>` or `<port> <gain> <volume>`".to_string()),
+            };
+            cap.map_err(|e| format!("{}: {e}", i + 1))?;
+        }
+        Ok(caps)
+    }
+
+    pub fn caps(&self) -> &[VolumeCap] {
+        &self.caps
+    }
+
+    /// Caps the volume of the dongle at `port` to `volume` when set to `gain`
+    pub fn set(&mut self, port: &str, gain: Gain, volume: Volume) {
+        self.remove(port, gain);
+        self.caps.push(VolumeCap {
+            port: port.to_string(),
+            gain,
+            volume,
+        });
+    }
+
+    /// Removes the cap, returning whether there was one
+    pub fn remove(&mut self, port: &str, gain: Gain) -> bool {
+        let len = self.caps.len();
+        self.caps.retain(|cap| cap.port != port || cap.gain != gain);
+        self.caps.len() != len
+    }
+
+    /// Loudest volume allowed on `device` when set to `gain`, if capped
+    pub fn cap(&self, device: &DeviceDescriptor, gain: Gain) -> Option<Volume> {
+        let port = device.port_path();
+        let find = |port: &str| {
+            self.caps
+                .iter()
+                .find(|cap| cap.port == port && cap.gain == gain)
+        };
+        find(&port).or_else(|| find(ANY_PORT)).map(|cap| cap.volume)
+    }
+
+    /// Whether any cap covers `device`, at whatever gain
+    pub fn covers(&self, device: &DeviceDescriptor) -> bool {
+        let port = device.port_path();
+        self.caps
+            .iter()
+            .any(|cap| cap.port == port || cap.port == ANY_PORT)
+    }
+}
+
+impl Display for VolumeCaps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "mode {}", self.mode)?;
+        for cap in &self.caps {
+            let gain = cap.gain.to_string().to_lowercase();
+            writeln!(f, "{} {gain} {:.1}dB", cap.port, cap.volume.to_db())?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::Moondrop;
+    use crate::device::BusAddress;
+    use crate::mock::{MemoryBackend, MemoryTransport, Registers};
+    use crate::selector::DeviceSelector;
+
+    const CAPS: &str = "\
+# comment
+mode fail
+* high -30.0dB
+3-1.2 low 80%
+";
+
+    fn dongle(ports: &[u8]) -> DeviceDescriptor {
+        DeviceDescriptor {
+            vendor_id: crate::MOONDROP_VID,
+            product_id: crate::DAWN_PRO_PID,
+            product: None,
+            address: BusAddress::new(3, 7),
+            port_chain: ports.to_vec(),
+        }
+    }
+
+    #[test]
+    fn parses_and_prints_caps() {
+        let caps = VolumeCaps::parse(CAPS).unwrap();
+        assert_eq!(caps.mode, CapMode::Fail);
+        assert_eq!(
+            caps.caps(),
+            [
+                VolumeCap {
+                    port: ANY_PORT.to_string(),
+                    gain: Gain::High,
+                    volume: Volume::from_db(-30.0),
+                },
+                VolumeCap {
+                    port: "3-1.2".to_string(),
+                    gain: Gain::Low,
+                    volume: Volume::from_percent(80),
+                },
+            ]
+        );
+        assert_eq!(VolumeCaps::parse(&caps.to_string()).unwrap(), caps);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for (contents, line) in [
+            ("mode loud", "1:"),
+            ("mode clamp\n* medium 50%", "2:"),
+            ("* low", "1:"),
+        ] {
+            let e = VolumeCaps::parse(contents).unwrap_err();
+            assert!(e.starts_with(line), "{contents:?}: {e}");
+        }
+    }
+
+    #[test]
+    fn port_cap_takes_precedence() {
+        let mut caps = VolumeCaps::parse(CAPS).unwrap();
+        caps.set(ANY_PORT, Gain::Low, Volume::from_percent(50));
+        let (capped, other) = (dongle(&[1, 2]), dongle(&[4]));
+
+        assert_eq!(caps.cap(&capped, Gain::Low), Some(Volume::from_percent(80)));
+        assert_eq!(caps.cap(&other, Gain::Low), Some(Volume::from_percent(50)));
+        assert_eq!(caps.cap(&capped, Gain::High), Some(Volume::from_db(-30.0)));
+        assert!(caps.remove(ANY_PORT, Gain::High));
+        assert!(!caps.remove(ANY_PORT, Gain::High));
+        assert_eq!(caps.cap(&capped, Gain::High), None);
+        assert!(caps.covers(&other));
+    }
+
+    /// A Dawn Pro at -32 dB and low gain, with its low gain capped at -20 dB
+    fn capped(mode: CapMode) -> (Arc<MemoryTransport>, Moondrop) {
+        let transport = Arc::new(MemoryTransport::new(Registers {
+            volume: Volume::from_db(-32.0).step(),
+            ..Registers::default()
+        }));
+        let mut backend = MemoryBackend::new();
+        backend.attach(transport.clone());
+        let mut moondrop = Moondrop::with_backend(backend).unwrap();
+        let mut caps = VolumeCaps {
+            mode,
+            ..VolumeCaps::default()
+        };
+        caps.set(ANY_PORT, Gain::Low, Volume::from_db(-20.0));
+        moondrop.set_caps(caps);
+        (transport, moondrop)
+    }
+
+    #[test]
+    fn clamps_louder_volumes() {
+        let (transport, mut moondrop) = capped(CapMode::Clamp);
+        let info = moondrop
+            .set_volume(&DeviceSelector::Any, Volume::from_db(-10.0))
+            .unwrap();
+        assert_eq!(info.volume, Volume::from_db(-20.0));
+        assert_eq!(transport.registers().volume, Volume::from_db(-20.0).step());
+
+        // quieter volumes aren't touched
+        let info = moondrop
+            .set_volume(&DeviceSelector::Any, Volume::from_db(-25.0))
+            .unwrap();
+        assert_eq!(info.volume, Volume::from_db(-25.0));
+    }
+
+    #[test]
+    fn fails_on_louder_volumes() {
+        let (transport, mut moondrop) = capped(CapMode::Fail);
+        let result = moondrop.set_volume(&DeviceSelector::Any, Volume::from_db(-10.0));
+        assert!(
+            matches!(
+                result,
+                Err(Error::OverCap {
+                    gain: Gain::Low,
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+        assert_eq!(transport.registers().volume, Volume::from_db(-32.0).step());
+
+        moondrop.set_cap_override(true);
+        let info = moondrop
+            .set_volume(&DeviceSelector::Any, Volume::from_db(-10.0))
+            .unwrap();
+        assert_eq!(info.volume, Volume::from_db(-10.0));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use crate::MOONDROP_VID;
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::gain::Gain;
+use crate::indicator_state::IndicatorState;
+use crate::pcap::{EventKind, PcapReader, URB_CONTROL};
+use crate::protocol::{self, Command, DecodeError};
+use crate::transport::{REQUEST_ID_READ, REQUEST_ID_WRITE, REQUEST_INDEX};
+use crate::volume::Volume;
+
+/// `bmRequestType` bits selecting vendor requests
+const REQUEST_TYPE_MASK: u8 = 0x60;
+const REQUEST_TYPE_VENDOR: u8 = 0x40;
+
+/// GET_DESCRIPTOR for the device descriptor, its reply carries the vendor id
+const REQUEST_TYPE_STANDARD_IN: u8 = 0x80;
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const DESCRIPTOR_DEVICE: u8 = 0x01;
+
+/// What a captured transfer means to a Moondrop dongle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Annotation {
+    /// A command this library knows
+    Command(Command),
+    /// Reply to [`Command::GetVolume`]
+    Volume(Volume),
+    /// Reply to [`Command::GetAny`]
+    Settings(Filter, Gain, IndicatorState),
+    /// Reply to a command that didn't decode or wasn't captured
+    Reply,
+    /// Doesn't decode, worth a closer look
+    Unknown(DecodeError),
+    /// The transfer failed with the given (negated) errno
+    Failed(i32),
+}
+
+impl Display for Annotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Annotation::Command(cmd) => write!(f, "{cmd}"),
+            Annotation::Volume(volume) => write!(f, "volume {volume}"),
+            Annotation::Settings(filter, gain, indicator_state) => write!(
+                f,
+                "filter {filter}, gain {gain}, indicator {indicator_state}"
+            ),
+            Annotation::Reply => write!(f, "reply"),
+            Annotation::Unknown(reason) => write!(f, "UNKNOWN: {reason}"),
+            Annotation::Failed(status) => write!(f, "failed with status {status}"),
+        }
+    }
+}
+
+/// A Moondrop vendor control transfer found in a capture
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedTransfer {
+    /// Time of submission, since the first packet of the capture
+    pub timestamp: Duration,
+    pub bus: u16,
+    pub device: u8,
+    /// [`REQUEST_ID_WRITE`] or [`REQUEST_ID_READ`]
+    pub request: u8,
+    /// Command sent with a write, or response received by a read
+    pub data: Vec<u8>,
+    pub annotation: Annotation,
+}
+
+struct Pending {
+    timestamp: Duration,
+    setup: [u8; 8],
+    data: Vec<u8>,
+}
+
+/// Extracts the Moondrop vendor transfers out of a usbmon pcap capture.
+///
+/// Devices are recognized by the vendor id of their device descriptor when the capture contains
+/// it. Otherwise any device issuing requests `0xA0`/`0xA1` with index `0x09A0` is assumed to be a
+/// Moondrop, which is what a capture started after plugging the dongle in looks like.
+pub fn read_transfers(reader: impl Read) -> io::Result<Vec<CapturedTransfer>> {
+    let mut pcap = PcapReader::new(reader)?;
+    let mut start = None;
+    let mut pending: HashMap<(u16, u8, u64), Pending> = HashMap::new();
+    let mut vendors: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut last_command: HashMap<(u16, u8), Option<Command>> = HashMap::new();
+    let mut transfers = Vec::new();
+
+    while let Some(packet) = pcap.next_packet()? {
+        let start = *start.get_or_insert(packet.timestamp);
+        if packet.transfer_type != URB_CONTROL {
+            continue;
+        }
+        let key = (packet.bus, packet.device, packet.id);
+        let device = (packet.bus, packet.device);
+        let submitted = match packet.kind {
+            EventKind::Submit => {
+                if let Some(setup) = packet.setup {
+                    pending.insert(
+                        key,
+                        Pending {
+                            timestamp: packet.timestamp.saturating_sub(start),
+                            setup,
+                            data: packet.data,
+                        },
+                    );
+                }
+                continue;
+            }
+            EventKind::Complete => match pending.remove(&key) {
+                Some(submitted) => submitted,
+                None => continue,
+            },
+        };
+
+        let [request_type, request, ..] = submitted.setup;
+        let index = u16::from_le_bytes([submitted.setup[4], submitted.setup[5]]);
+        if request_type == REQUEST_TYPE_STANDARD_IN
+            && request == REQUEST_GET_DESCRIPTOR
+            && submitted.setup[3] == DESCRIPTOR_DEVICE
+            && packet.data.len() >= 10
+        {
+            vendors.insert(device, u16::from_le_bytes([packet.data[8], packet.data[9]]));
+            continue;
+        }
+        if request_type & REQUEST_TYPE_MASK != REQUEST_TYPE_VENDOR
+            || index != REQUEST_INDEX
+            || vendors.get(&device).is_some_and(|&vid| vid != MOONDROP_VID)
+        {
+            continue;
+        }
+
+        let last = last_command.entry(device).or_default();
+        let (data, annotation) = match request {
+            _ if packet.status != 0 => (submitted.data, Annotation::Failed(packet.status)),
+            REQUEST_ID_WRITE => {
+                let annotation = match Command::parse(&submitted.data) {
+                    Ok(cmd) => {
+                        *last = Some(cmd);
+                        Annotation::Command(cmd)
+                    }
+                    Err(reason) => {
+                        *last = None;
+                        Annotation::Unknown(reason)
+                    }
+                };
+                (submitted.data, annotation)
+            }
+            REQUEST_ID_READ => {
+                let annotation = annotate_reply(last.take(), &packet.data);
+                (packet.data, annotation)
+            }
+            _ => continue,
+        };
+        transfers.push(CapturedTransfer {
+            timestamp: submitted.timestamp,
+            bus: packet.bus,
+            device: packet.device,
+            request,
+            data,
+            annotation,
+        });
+    }
+    Ok(transfers)
+}
+
+fn annotate_reply(command: Option<Command>, data: &[u8]) -> Annotation {
+    let decoded = match command {
+        Some(Command::GetVolume) => protocol::decode_volume(data).map(Annotation::Volume),
+        Some(Command::GetAny) => protocol::decode_any(data)
+            .map(|(filter, gain, state)| Annotation::Settings(filter, gain, state)),
+        _ => return Annotation::Reply,
+    };
+    match decoded {
+        Ok(annotation) => annotation,
+        Err(Error::Decode { reason, .. }) => Annotation::Unknown(reason),
+        Err(_) => Annotation::Reply,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::{PcapWriter, UsbPacket};
+    use crate::protocol::{GET_ANY, SET_GAIN};
+
+    /// Submission and completion of a vendor transfer to device 3:7
+    fn transfer(id: u64, request: u8, data: &[u8], reply: &[u8], status: i32) -> [UsbPacket; 2] {
+        let (request_type, endpoint) = match request {
+            REQUEST_ID_WRITE => (0x43, 0x00),
+            _ => (0xc3, 0x80),
+        };
+        let length = data.len().max(reply.len()) as u16;
+        let mut setup = [request_type, request, 0, 0, 0, 0, 0, 0];
+        setup[4..6].copy_from_slice(&REQUEST_INDEX.to_le_bytes());
+        setup[6..8].copy_from_slice(&length.to_le_bytes());
+        let submit = UsbPacket {
+            id,
+            kind: EventKind::Submit,
+            transfer_type: URB_CONTROL,
+            endpoint,
+            device: 7,
+            bus: 3,
+            setup: Some(setup),
+            timestamp: Duration::from_millis(1000 + id),
+            status: -115,
+            length: length as u32,
+            data: data.to_vec(),
+        };
+        let complete = UsbPacket {
+            kind: EventKind::Complete,
+            setup: None,
+            status,
+            data: reply.to_vec(),
+            ..submit.clone()
+        };
+        [submit, complete]
+    }
+
+    fn read(transfers: &[[UsbPacket; 2]]) -> Vec<CapturedTransfer> {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for packet in transfers.iter().flatten() {
+            writer.write(packet).unwrap();
+        }
+        read_transfers(&writer.into_inner()[..]).unwrap()
+    }
+
+    #[test]
+    fn annotates_commands_and_replies() {
+        let reply = [0xc0, 0xa5, 0xa3, 0x00, 0x01, 0x02, 0x00];
+        let transfers = read(&[
+            transfer(1, REQUEST_ID_WRITE, &GET_ANY, &[], 0),
+            transfer(2, REQUEST_ID_READ, &[], &reply, 0),
+            transfer(3, REQUEST_ID_WRITE, &[0xc0, 0xa5, 0x09, 0x00], &[], 0),
+            transfer(
+                4,
+                REQUEST_ID_WRITE,
+                &[SET_GAIN[0], SET_GAIN[1], SET_GAIN[2], 1],
+                &[],
+                -32,
+            ),
+        ]);
+        let annotations: Vec<(u8, &Annotation)> = transfers
+            .iter()
+            .map(|t| (t.request, &t.annotation))
+            .collect();
+        assert_eq!(
+            annotations,
+            [
+                (REQUEST_ID_WRITE, &Annotation::Command(Command::GetAny)),
+                (
+                    REQUEST_ID_READ,
+                    &Annotation::Settings(Filter::default(), Gain::High, IndicatorState::Disabled)
+                ),
+                (
+                    REQUEST_ID_WRITE,
+                    &Annotation::Unknown(DecodeError::UnknownCommand { opcode: 0x09 })
+                ),
+                (REQUEST_ID_WRITE, &Annotation::Failed(-32)),
+            ]
+        );
+        assert_eq!(transfers[1].data, reply);
+        assert_eq!(transfers[1].timestamp, Duration::from_millis(1));
+        assert!(transfers.iter().all(|t| (t.bus, t.device) == (3, 7)));
+    }
+
+    #[test]
+    fn reply_without_a_captured_command_is_left_alone() {
+        let transfers = read(&[transfer(
+            1,
+            REQUEST_ID_READ,
+            &[],
+            &[0xc0, 0xa5, 0xa2, 0, 0x42, 0, 0],
+            0,
+        )]);
+        assert_eq!(transfers[0].annotation, Annotation::Reply);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::MoondropInfo;
+use crate::error::Result;
+use crate::filter::Filter;
+use crate::gain::Gain;
+use crate::indicator_state::IndicatorState;
+use crate::model::Model;
+use crate::protocol::{self, DeviceState, GET_ANY, GET_VOLUME, RESPONSE_LEN, SET_VOLUME};
+use crate::setting::Setting;
+use crate::transport::Transport;
+use crate::volume::Volume;
+
+/// Location of a dongle on the USB bus, unique for as long as it stays attached
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BusAddress {
+    pub bus: u8,
+    pub address: u8,
+}
+
+impl BusAddress {
+    pub fn new(bus: u8, address: u8) -> Self {
+        Self { bus, address }
+    }
+}
+
+impl Display for BusAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.bus, self.address)
+    }
+}
+
+/// Static description of an attached dongle, as reported by the USB stack
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub product: Option<String>,
+    pub address: BusAddress,
+    pub port_chain: Vec<u8>,
+}
+
+impl DeviceDescriptor {
+    /// Entry of the model registry this dongle belongs to, if any
+    pub fn model(&self) -> Option<&'static Model> {
+        Model::lookup(self.vendor_id, self.product_id)
+    }
+
+    /// USB port path as used by sysfs, ex. `3-1.2`
+    pub fn port_path(&self) -> String {
+        let ports: Vec<String> = self.port_chain.iter().map(u8::to_string).collect();
+        format!("{}-{}", self.address.bus, ports.join("."))
+    }
+
+    pub fn name(&self) -> String {
+        match (&self.product, self.model()) {
+            (Some(name), _) => name.clone(),
+            (None, Some(model)) => model.name.to_string(),
+            (None, None) => "Unknown".to_string(),
+        }
+    }
+}
+
+/// Session with a single dongle.
+///
+/// The underlying [`Transport`] is shared between clones and keeps the device open, so holding on
+/// to a `MoondropDevice` avoids reopening and re-enumerating for every transfer.
+#[derive(Clone, Debug)]
+pub struct MoondropDevice {
+    pub descriptor: DeviceDescriptor,
+    transport: Arc<dyn Transport>,
+}
+
+impl MoondropDevice {
+    pub fn new(descriptor: DeviceDescriptor, transport: Arc<dyn Transport>) -> Self {
+        Self {
+            descriptor,
+            transport,
+        }
+    }
+
+    pub fn transport(&self) -> &dyn Transport {
+        self.transport.as_ref()
+    }
+
+    pub(crate) fn shared_transport(&self) -> Arc<dyn Transport> {
+        self.transport.clone()
+    }
+
+    pub async fn get_volume(&self) -> Result<Volume> {
+        let data = self.read(&GET_VOLUME, RESPONSE_LEN as u16).await?;
+        protocol::decode_volume(&data)
+    }
+
+    /// Reads every setting the dongle reports
+    pub async fn get_state(&self) -> Result<DeviceState> {
+        let volume = self.read(&GET_VOLUME, RESPONSE_LEN as u16).await?;
+        let any = self.read(&GET_ANY, RESPONSE_LEN as u16).await?;
+        DeviceState::decode(&volume, &any)
+    }
+
+    pub async fn get_all(&self) -> Result<MoondropInfo> {
+        let state = self.get_state().await?;
+        Ok(MoondropInfo::new(
+            self.descriptor.name(),
+            self.descriptor.address,
+            state,
+        ))
+    }
+
+    /// Reads a single [`Setting`]
+    pub async fn get<S: Setting>(&self) -> Result<S> {
+        let any = self.read(&GET_ANY, RESPONSE_LEN as u16).await?;
+        protocol::decode_setting(&any)
+    }
+
+    /// Writes a single [`Setting`], without reading it back
+    pub async fn set<S: Setting>(&self, value: S) -> Result<()> {
+        self.write(&command(S::COMMAND, value.to_u8())).await
+    }
+
+    pub async fn set_gain(&self, gain: Gain) -> Result<()> {
+        self.set(gain).await
+    }
+
+    pub async fn set_volume(&self, level: Volume) -> Result<()> {
+        self.write(&command(SET_VOLUME, level.to_payload())).await
+    }
+
+    pub async fn set_filter(&self, filter: Filter) -> Result<()> {
+        self.set(filter).await
+    }
+
+    pub async fn set_indicator_state(&self, indicator_state: IndicatorState) -> Result<()> {
+        self.set(indicator_state).await
+    }
+
+    /// Sends `cmd` and reads back up to `length` bytes of response
+    pub async fn read(&self, cmd: &[u8], length: u16) -> Result<Vec<u8>> {
+        self.transport.control_out(cmd).await?;
+        self.transport.control_in(length).await
+    }
+
+    /// Sends `cmd` without reading a response
+    pub async fn write(&self, cmd: &[u8]) -> Result<()> {
+        log::debug!("{} command: {:02x?}", self.descriptor.address, cmd);
+        self.transport.control_out(cmd).await
+    }
+}
+
+pub(crate) fn command(cmd: [u8; 3], value: u8) -> Vec<u8> {
+    let mut cmd = Vec::from(cmd);
+    cmd.push(value);
+    cmd
+}
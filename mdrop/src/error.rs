@@ -0,0 +1,194 @@
+use std::fmt::Display;
+use std::io;
+
+use nusb::transfer::TransferError;
+
+use crate::gain::Gain;
+use crate::protocol::DecodeError;
+use crate::selector::DeviceSelector;
+use crate::volume::Volume;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// No Moondrop dongle is attached
+    NoDevice,
+    /// The device selector could not be parsed
+    InvalidSelector(String),
+    /// The name doesn't match any value of `setting`
+    InvalidValue {
+        setting: &'static str,
+        value: String,
+    },
+    /// No attached dongle matches the selector
+    NoMatch(DeviceSelector),
+    /// More than one attached dongle matches the selector
+    Ambiguous {
+        selector: DeviceSelector,
+        matches: usize,
+    },
+    /// Opening the device was refused, usually because the udev rule is missing
+    PermissionDenied(io::Error),
+    /// Opening the device failed for any other reason
+    Open(io::Error),
+    /// Listing or watching USB devices failed
+    Enumeration(io::Error),
+    /// The dongle stalled the control transfer
+    Stall,
+    /// The dongle went away in the middle of a transfer
+    DeviceGone,
+    /// The control transfer failed in some other way
+    Transfer(TransferError),
+    /// The dongle's reply could not be decoded, `data` holds the raw bytes
+    Decode { reason: DecodeError, data: Vec<u8> },
+    /// The device is not a Moondrop dongle this library can drive
+    UnsupportedDevice { vendor_id: u16, product_id: u16 },
+    /// Creating the trace files failed
+    Trace(io::Error),
+    /// Reading the volume caps failed
+    Caps(io::Error),
+    /// The volume is louder than what [`crate::caps::VolumeCaps`] allow at `gain`
+    OverCap {
+        gain: Gain,
+        requested: Volume,
+        cap: Volume,
+    },
+    /// Reading or writing the volumes of muted dongles failed
+    MuteState(io::Error),
+    /// Reading back a setting after writing it still shows another value
+    Mismatch {
+        setting: &'static str,
+        expected: String,
+        actual: String,
+    },
+    /// Applying several settings failed at `setting`, `rollback` holds the first error hit while
+    /// restoring the previous settings, if any
+    Apply {
+        setting: &'static str,
+        error: Box<Error>,
+        rollback: Option<Box<Error>>,
+    },
+    /// The dongle's model has no such setting or value
+    UnsupportedSetting { model: &'static str },
+}
+
+impl Error {
+    pub(crate) fn open(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(err),
+            _ => Error::Open(err),
+        }
+    }
+
+    pub(crate) fn decode(reason: DecodeError, data: &[u8]) -> Self {
+        Error::Decode {
+            reason,
+            data: data.to_vec(),
+        }
+    }
+}
+
+impl From<TransferError> for Error {
+    fn from(err: TransferError) -> Self {
+        match err {
+            TransferError::Stall => Error::Stall,
+            TransferError::Disconnected => Error::DeviceGone,
+            err => Error::Transfer(err),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoDevice => write!(f, "No Moondrop dongle connected."),
+            Error::InvalidSelector(s) => write!(
+                f,
+                "invalid device selector `{s}`, expected bus:address, port path, name or index"
+            ),
+            Error::InvalidValue { setting, value } => write!(f, "unknown {setting} `{value}`"),
+            Error::NoMatch(selector) => write!(f, "no Moondrop dongle matches {selector}"),
+            Error::Ambiguous { selector, matches } => {
+                write!(f, "{matches} Moondrop dongles match {selector}")
+            }
+            Error::PermissionDenied(err) => write!(
+                f,
+                "permission denied while opening the dongle ({err}), is the udev rule installed?"
+            ),
+            Error::Open(err) => write!(f, "failed to open the dongle: {err}"),
+            Error::Enumeration(err) => write!(f, "failed to list USB devices: {err}"),
+            Error::Trace(err) => write!(f, "failed to create trace file: {err}"),
+            Error::Caps(err) => write!(f, "failed to read the volume caps: {err}"),
+            Error::OverCap {
+                gain,
+                requested,
+                cap,
+            } => write!(
+                f,
+                "refusing volume {requested}, it is over the {cap} cap at {gain} gain"
+            ),
+            Error::MuteState(err) => write!(f, "failed to access the mute state: {err}"),
+            Error::Stall => write!(f, "the dongle stalled the control transfer"),
+            Error::DeviceGone => write!(f, "the dongle was disconnected"),
+            Error::Transfer(err) => write!(f, "control transfer failed: {err}"),
+            Error::Decode { reason, data } => {
+                write!(
+                    f,
+                    "malformed response from the dongle ({reason}): {data:02x?}"
+                )
+            }
+            Error::UnsupportedDevice {
+                vendor_id,
+                product_id,
+            } => write!(
+                f,
+                "refusing to write to unsupported device {vendor_id:04x}:{product_id:04x}"
+            ),
+            Error::Mismatch {
+                setting,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "the dongle didn't take the new {setting}: expected {expected}, it reports {actual}"
+            ),
+            Error::Apply {
+                setting,
+                error,
+                rollback: None,
+            } => write!(
+                f,
+                "failed to set {setting}: {error}, previous settings were restored"
+            ),
+            Error::Apply {
+                setting,
+                error,
+                rollback: Some(rollback),
+            } => write!(
+                f,
+                "failed to set {setting}: {error}, restoring previous settings failed too: {rollback}"
+            ),
+            Error::UnsupportedSetting { model } => {
+                write!(f, "{model} does not support this setting")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PermissionDenied(err)
+            | Error::Open(err)
+            | Error::Enumeration(err)
+            | Error::Trace(err)
+            | Error::Caps(err)
+            | Error::MuteState(err) => Some(err),
+            Error::Transfer(err) => Some(err),
+            Error::Decode { reason, .. } => Some(reason),
+            Error::Apply { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
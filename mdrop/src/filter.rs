@@ -1,44 +1,114 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
-use clap::ValueEnum;
+use crate::MoondropInfo;
+use crate::error::Error;
+use crate::model::Capabilities;
+use crate::protocol::{DecodeError, SET_FILTER};
+use crate::setting::{self, Setting};
 
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Filter {
     #[default]
-    #[clap(alias = "froll")]
+    #[cfg_attr(feature = "cli", value(alias = "froll"))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "froll", alias = "fast-roll-off-low-latency")
+    )]
     FastRollOffLowLatency = 0,
-    #[clap(alias = "fropc")]
+    #[cfg_attr(feature = "cli", value(alias = "fropc"))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "fropc", alias = "fast-roll-off-phase-compensated")
+    )]
     FastRollOffPhaseCompensated = 1,
-    #[clap(alias = "sroll")]
+    #[cfg_attr(feature = "cli", value(alias = "sroll"))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "sroll", alias = "slow-roll-off-low-latency")
+    )]
     SlowRollOffLowLatency = 2,
-    #[clap(alias = "sropc")]
+    #[cfg_attr(feature = "cli", value(alias = "sropc"))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "sropc", alias = "slow-roll-off-phase-compensated")
+    )]
     SlowRollOffPhaseCompensated = 3,
-    #[clap(alias = "no")]
+    #[cfg_attr(feature = "cli", value(alias = "no"))]
+    #[cfg_attr(feature = "serde", serde(rename = "no", alias = "non-oversampling"))]
     NonOversampling = 4,
 }
 
 impl Filter {
-    pub const ALL: [Filter; 5] = [
+    /// Lenient conversion from the raw value, unknown values fall back to the default
+    pub fn from_lossy(value: u8) -> Self {
+        Self::try_from(value).unwrap_or_default()
+    }
+}
+
+impl Setting for Filter {
+    const NAME: &'static str = "filter";
+    const COMMAND: [u8; 3] = SET_FILTER;
+    const RESPONSE_IDX: usize = 3;
+    const ALL: &'static [Self] = &[
         Filter::FastRollOffLowLatency,
         Filter::FastRollOffPhaseCompensated,
         Filter::SlowRollOffLowLatency,
         Filter::SlowRollOffPhaseCompensated,
         Filter::NonOversampling,
     ];
-}
 
-impl From<u8> for Filter {
-    fn from(value: u8) -> Self {
-        match value {
-            0 => Filter::FastRollOffLowLatency,
-            1 => Filter::FastRollOffPhaseCompensated,
-            2 => Filter::SlowRollOffLowLatency,
-            3 => Filter::SlowRollOffPhaseCompensated,
-            4 => Filter::NonOversampling,
-            _ => Filter::FastRollOffLowLatency,
+    fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Filter::FastRollOffLowLatency => "froll",
+            Filter::FastRollOffPhaseCompensated => "fropc",
+            Filter::SlowRollOffLowLatency => "sroll",
+            Filter::SlowRollOffPhaseCompensated => "sropc",
+            Filter::NonOversampling => "no",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Filter::FastRollOffLowLatency => "fast-roll-off-low-latency",
+            Filter::FastRollOffPhaseCompensated => "fast-roll-off-phase-compensated",
+            Filter::SlowRollOffLowLatency => "slow-roll-off-low-latency",
+            Filter::SlowRollOffPhaseCompensated => "slow-roll-off-phase-compensated",
+            Filter::NonOversampling => "non-oversampling",
         }
     }
+
+    fn supported(capabilities: &Capabilities) -> &[Self] {
+        capabilities.filters
+    }
+
+    fn of(info: &MoondropInfo) -> Self {
+        info.filter
+    }
+}
+
+impl TryFrom<u8> for Filter {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        setting::from_u8(value)
+    }
+}
+
+/// Parses the code or the name of a value, ignoring case
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        setting::parse(s)
+    }
 }
 
 impl Display for Filter {
@@ -1,9 +1,17 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
-use clap::ValueEnum;
+use crate::MoondropInfo;
+use crate::error::Error;
+use crate::model::Capabilities;
+use crate::protocol::{DecodeError, SET_GAIN};
+use crate::setting::{self, Setting};
 
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum Gain {
     #[default]
     Low = 0,
@@ -11,20 +19,57 @@ pub enum Gain {
 }
 
 impl Gain {
-    pub const ALL: [Gain; 2] = [
-        Gain::Low,
-        Gain::High,
-    ];
+    /// Lenient conversion from the raw value, unknown values fall back to the default
+    pub fn from_lossy(value: u8) -> Self {
+        Self::try_from(value).unwrap_or_default()
+    }
 }
 
-impl From<u8> for Gain {
-    fn from(value: u8) -> Self {
-        match value {
-            0 => Gain::Low,
-            1 => Gain::High,
-            _ => Gain::Low,
+impl Setting for Gain {
+    const NAME: &'static str = "gain";
+    const COMMAND: [u8; 3] = SET_GAIN;
+    const RESPONSE_IDX: usize = 4;
+    const ALL: &'static [Self] = &[Gain::Low, Gain::High];
+
+    fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Gain::Low => "low",
+            Gain::High => "high",
         }
     }
+
+    fn supported(capabilities: &Capabilities) -> &[Self] {
+        capabilities.gains
+    }
+
+    fn of(info: &MoondropInfo) -> Self {
+        info.gain
+    }
+
+    fn gain(self) -> Option<Gain> {
+        Some(self)
+    }
+}
+
+impl TryFrom<u8> for Gain {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        setting::from_u8(value)
+    }
+}
+
+/// Parses the code or the name of a value, ignoring case
+impl FromStr for Gain {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        setting::parse(s)
+    }
 }
 
 impl Display for Gain {
@@ -1,9 +1,17 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
-use clap::ValueEnum;
+use crate::MoondropInfo;
+use crate::error::Error;
+use crate::model::Capabilities;
+use crate::protocol::{DecodeError, SET_INDICATOR_STATE};
+use crate::setting::{self, Setting};
 
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum IndicatorState {
     #[default]
     Enabled = 0,
@@ -12,22 +20,58 @@ pub enum IndicatorState {
 }
 
 impl IndicatorState {
-    pub const ALL: [IndicatorState; 3] = [
+    /// Lenient conversion from the raw value, unknown values fall back to the default
+    pub fn from_lossy(value: u8) -> Self {
+        Self::try_from(value).unwrap_or_default()
+    }
+}
+
+impl Setting for IndicatorState {
+    const NAME: &'static str = "indicator state";
+    const COMMAND: [u8; 3] = SET_INDICATOR_STATE;
+    const RESPONSE_IDX: usize = 5;
+    const ALL: &'static [Self] = &[
         IndicatorState::Enabled,
         IndicatorState::DisabledTemp,
         IndicatorState::Disabled,
     ];
-}
 
-impl From<u8> for IndicatorState {
-    fn from(value: u8) -> Self {
-        match value {
-            0 => IndicatorState::Enabled,
-            1 => IndicatorState::DisabledTemp,
-            2 => IndicatorState::Disabled,
-            _ => IndicatorState::Enabled,
+    fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            IndicatorState::Enabled => "enabled",
+            IndicatorState::DisabledTemp => "disabled-temp",
+            IndicatorState::Disabled => "disabled",
         }
     }
+
+    fn supported(capabilities: &Capabilities) -> &[Self] {
+        capabilities.indicator_states
+    }
+
+    fn of(info: &MoondropInfo) -> Self {
+        info.indicator_state
+    }
+}
+
+impl TryFrom<u8> for IndicatorState {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        setting::from_u8(value)
+    }
+}
+
+/// Parses the code or the name of a value, ignoring case
+impl FromStr for IndicatorState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        setting::parse(s)
+    }
 }
 
 impl Display for IndicatorState {
@@ -1,270 +1,1052 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::hash::Hash;
-use std::sync::mpsc;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures_lite::future;
-use nusb::hotplug::HotplugEvent;
-use nusb::transfer::{ControlIn, ControlOut};
-use nusb::{DeviceId, DeviceInfo};
-use tabled::Tabled;
 
+use crate::auto_gain::AutoGain;
+use crate::caps::{CapMode, VolumeCaps};
+use crate::device::{BusAddress, MoondropDevice, command};
+pub use crate::error::{Error, Result};
 use crate::filter::Filter;
 use crate::gain::Gain;
 use crate::indicator_state::IndicatorState;
-use crate::volume::Volume;
+use crate::model::Capabilities;
+use crate::monitor::Monitor;
+use crate::mute::MuteMemory;
+use crate::protocol::{DeviceState, SET_VOLUME};
+use crate::ramp::Cancel;
+use crate::selector::DeviceSelector;
+use crate::setting::Setting;
+use crate::settings::{Change, Settings};
+use crate::trace::{Tracer, TracingBackend};
+use crate::transport::{Backend, NusbBackend};
+use crate::volume::{DB_PER_STEP, Volume, VolumeDelta};
+use crate::watch::Watch;
 
+pub mod auto_gain;
+pub mod caps;
+pub mod capture;
+pub mod device;
+mod error;
 pub mod filter;
 pub mod gain;
 pub mod indicator_state;
+pub mod mock;
+pub mod model;
+pub mod monitor;
+pub mod mute;
+pub mod pcap;
+pub mod protocol;
+pub mod ramp;
+pub mod selector;
+pub mod setting;
+pub mod settings;
+pub mod trace;
+pub mod transport;
 pub mod volume;
+pub mod watch;
 
 pub const MOONDROP_VID: u16 = 0x2fc6;
 pub const DAWN_PRO_PID: u16 = 0xf06a;
 
-const REQUEST_INDEX: u16 = 0x09A0;
-const REQUEST_VALUE: u16 = 0x0000;
+/// Default number of times a setter writes again when the change didn't take effect
+pub const DEFAULT_VERIFY_RETRIES: u32 = 2;
 
-const REQUEST_ID_WRITE: u8 = 0xA0;
-const REQUEST_ID_READ: u8 = 0xA1;
+/// Non-blocking access to the attached dongles, every transfer is returned as a future
+#[derive(Clone, Debug)]
+pub struct AsyncMoondrop {
+    backend: Arc<dyn Backend>,
+    pub devices: BTreeMap<BusAddress, MoondropDevice>,
+    allow_unknown: bool,
+    verify_retries: u32,
+    mute: MuteMemory,
+    caps: VolumeCaps,
+    cap_override: bool,
+    keep_loudness: bool,
+    auto_gain: Option<AutoGain>,
+    gain_offset: Option<f32>,
+}
 
-const GET_ANY: [u8; 3] = [0xC0, 0xA5, 0xA3];
-const GET_VOLUME: [u8; 3] = [0xC0, 0xA5, 0xA2];
-const SET_FILTER: [u8; 3] = [0xC0, 0xA5, 0x01];
-const SET_GAIN: [u8; 3] = [0xC0, 0xA5, 0x02];
-const SET_VOLUME: [u8; 3] = [0xC0, 0xA5, 0x04];
-const SET_INDICATOR_STATE: [u8; 3] = [0xC0, 0xA5, 0x06];
+impl AsyncMoondrop {
+    /// Talks to the attached hardware, tracing to the pcap named by [`trace::TRACE_ENV`] if set.
+    ///
+    /// Muted volumes are kept in [`MuteMemory::from_env`] and the caps are read from
+    /// [`VolumeCaps::from_env`].
+    pub fn new() -> Result<Self> {
+        match std::env::var_os(trace::TRACE_ENV) {
+            Some(path) => Self::with_trace(path),
+            None => Self::with_user_state(NusbBackend),
+        }
+    }
 
-const VOLUME_IDX: usize = 4;
-const FILTER_IDX: usize = 3;
-const GAIN_IDX: usize = 4;
-const INDICATOR_STATE_IDX: usize = 5;
+    /// Like [`AsyncMoondrop::new`], but traces to the pcap at `path` whatever
+    /// [`trace::TRACE_ENV`] says, see [`Tracer::create`]
+    pub fn with_trace(path: impl AsRef<Path>) -> Result<Self> {
+        let tracer = Tracer::create(path).map_err(Error::Trace)?;
+        Self::with_user_state(TracingBackend::new(NusbBackend, tracer))
+    }
 
-#[derive(Clone, Debug)]
-pub struct Moondrop {
-    pub devices: HashMap<DeviceId, DeviceInfo>,
-    single: Option<DeviceId>,
-}
+    /// `backend` with the user's mute memory and caps
+    fn with_user_state(backend: impl Backend + 'static) -> Result<Self> {
+        let mut moondrop = Self::with_backend(backend)?;
+        moondrop.set_mute_memory(MuteMemory::from_env());
+        moondrop.set_caps(VolumeCaps::from_env().map_err(Error::Caps)?);
+        Ok(moondrop)
+    }
 
-impl Moondrop {
-    pub fn new() -> Self {
-        let devices = Self::refresh();
-        let single = if devices.len() == 1 {
-            devices.keys().next().cloned()
-        } else {
-            None
+    /// Creates an `AsyncMoondrop` that reaches its dongles through `backend`.
+    ///
+    /// Nothing of the user's is read or written: muted volumes are only kept in memory and there are
+    /// no volume caps.
+    pub fn with_backend(backend: impl Backend + 'static) -> Result<Self> {
+        let backend: Arc<dyn Backend> = Arc::new(backend);
+        let devices = Self::enumerate(backend.as_ref())?;
+        Ok(Self {
+            backend,
+            devices,
+            allow_unknown: false,
+            verify_retries: DEFAULT_VERIFY_RETRIES,
+            mute: MuteMemory::in_memory(),
+            caps: VolumeCaps::default(),
+            cap_override: false,
+            keep_loudness: false,
+            auto_gain: None,
+            gain_offset: None,
+        })
+    }
+
+    /// Allows writes to dongles that aren't a verified entry of [`model::MODELS`]
+    pub fn set_allow_unknown(&mut self, allow: bool) {
+        self.allow_unknown = allow;
+    }
+
+    /// How many times setters write again when reading back shows the change didn't take effect
+    pub fn set_verify_retries(&mut self, retries: u32) {
+        self.verify_retries = retries;
+    }
+
+    /// Where [`AsyncMoondrop::mute`] keeps the volume to restore, see [`AsyncMoondrop::new`] and
+    /// [`AsyncMoondrop::with_backend`] for the default
+    pub fn set_mute_memory(&mut self, memory: MuteMemory) {
+        self.mute = memory;
+    }
+
+    /// Volume ceilings enforced by the setters, see [`AsyncMoondrop::new`] and
+    /// [`AsyncMoondrop::with_backend`] for the default
+    pub fn set_caps(&mut self, caps: VolumeCaps) {
+        self.caps = caps;
+    }
+
+    /// Lets the setters go over the volume caps
+    pub fn set_cap_override(&mut self, allow: bool) {
+        self.cap_override = allow;
+    }
+
+    /// Makes [`AsyncMoondrop::set_gain`] compensate the volume so the loudness stays the same.
+    ///
+    /// This needs to know the gain offset, see [`AsyncMoondrop::set_gain_offset`], without it
+    /// switching the gain fails with [`Error::UnsupportedSetting`].
+    pub fn set_keep_loudness(&mut self, keep: bool) {
+        self.keep_loudness = keep;
+    }
+
+    /// How many dB louder [`Gain::High`] is than [`Gain::Low`] at the same volume, a positive
+    /// number. It takes precedence over the model's [`model::Capabilities::gain_offset_db`],
+    /// `None` goes back to that.
+    pub fn set_gain_offset(&mut self, offset_db: Option<f32>) {
+        self.gain_offset = offset_db;
+    }
+
+    /// Lets [`AsyncMoondrop::set_loudness`], [`AsyncMoondrop::set_volume`] and
+    /// [`AsyncMoondrop::adjust_volume`] pick the gain, `None` leaves it to the user.
+    ///
+    /// Picking the gain needs to know the gain offset, see [`AsyncMoondrop::set_gain_offset`].
+    /// Without it this fails with [`Error::UnsupportedSetting`] for the attached dongles, and the
+    /// volume setters do for ones attached later.
+    pub fn set_auto_gain(&mut self, auto_gain: Option<AutoGain>) -> Result<()> {
+        if auto_gain.is_some() {
+            for device in self.devices.values() {
+                self.gain_offset(device)?;
+            }
+        }
+        self.auto_gain = auto_gain;
+        Ok(())
+    }
+
+    /// Lists every dongle matched by `selector`
+    pub async fn detect(&self, selector: &DeviceSelector) -> Result<Vec<MoondropInfo>> {
+        let devices = self.select(selector);
+        if devices.is_empty() && *selector != DeviceSelector::Any {
+            return Err(Error::NoMatch(selector.clone()));
+        }
+        let mut infos = Vec::with_capacity(devices.len());
+        for device in devices {
+            infos.push(device.get_all().await?);
+        }
+        Ok(infos)
+    }
+
+    /// Resolves `selector` to exactly one attached dongle
+    pub fn device(&self, selector: &DeviceSelector) -> Result<&MoondropDevice> {
+        let devices = self.select(selector);
+        match devices[..] {
+            [device] => Ok(device),
+            [] if *selector == DeviceSelector::Any => Err(Error::NoDevice),
+            [] => Err(Error::NoMatch(selector.clone())),
+            _ => Err(Error::Ambiguous {
+                selector: selector.clone(),
+                matches: devices.len(),
+            }),
+        }
+    }
+
+    pub async fn get_volume(&self, selector: &DeviceSelector) -> Result<Volume> {
+        self.device(selector)?.get_volume().await
+    }
+
+    /// Reads a single [`Setting`], ex. `get::<Filter>(selector)`
+    pub async fn get<S: Setting>(&self, selector: &DeviceSelector) -> Result<S> {
+        self.device(selector)?.get().await
+    }
+
+    pub async fn get_filter(&self, selector: &DeviceSelector) -> Result<Filter> {
+        self.get(selector).await
+    }
+
+    pub async fn get_gain(&self, selector: &DeviceSelector) -> Result<Gain> {
+        self.get(selector).await
+    }
+
+    pub async fn get_indicator_state(&self, selector: &DeviceSelector) -> Result<IndicatorState> {
+        self.get(selector).await
+    }
+
+    pub async fn get_all(&self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        self.device(selector)?.get_all().await
+    }
+
+    /// Switches the gain, with [`AsyncMoondrop::set_keep_loudness`] the volume moves by the gain
+    /// offset the other way
+    pub async fn set_gain(
+        &mut self,
+        selector: &DeviceSelector,
+        gain: Gain,
+    ) -> Result<MoondropInfo> {
+        if !self.keep_loudness {
+            return self.write_gain(selector, gain).await;
+        }
+        let device = self.resolve(selector)?;
+        let info = device.get_all().await?;
+        if info.gain == gain {
+            return Ok(info);
+        }
+        let offset = self.gain_offset(&device)?;
+        let delta = match gain {
+            Gain::High => -offset,
+            Gain::Low => offset,
         };
-        Self { devices, single }
-    }
-
-    pub fn watch(&mut self, tx: mpsc::Sender<Option<MoondropInfo>>) {
-        let watch = nusb::watch_devices().unwrap();
-        for event in futures_lite::stream::block_on(watch) {
-            match event {
-                HotplugEvent::Connected(di) => {
-                    if di.vendor_id() == MOONDROP_VID {
-                        self.single = Some(di.id());
-                        self.devices.insert(di.id(), di);
-                        tx.send(self.get_all()).expect("connect: send failed");
-                        log::debug!("devices: {:?}", self.devices);
-                    }
+        let volume = info.volume.adjust(VolumeDelta::Db(delta));
+        let compensated = volume.to_db() - info.volume.to_db();
+        if (compensated - delta).abs() > DB_PER_STEP / 2.0 {
+            log::warn!(
+                "volume {volume:#} only makes up for {compensated:+.1} dB of {delta:+.1} dB"
+            );
+        }
+        let settings = Settings {
+            volume: Some(volume),
+            gain: Some(gain),
+            ..Settings::default()
+        };
+        let selector = DeviceSelector::BusAddress(device.descriptor.address);
+        self.apply(&selector, &settings).await
+    }
+
+    async fn write_gain(&mut self, selector: &DeviceSelector, gain: Gain) -> Result<MoondropInfo> {
+        log::debug!("Gain: {gain}");
+        if let Some((device, volume)) = self.over_cap(selector, gain).await? {
+            let cap = self.caps.cap(&device.descriptor, gain).unwrap_or(volume);
+            match self.caps.mode {
+                CapMode::Fail => {
+                    return Err(Error::OverCap {
+                        gain,
+                        requested: volume,
+                        cap,
+                    });
+                }
+                CapMode::Clamp => {
+                    log::warn!("volume {volume} is over the {cap} cap at {gain} gain, lowering it");
+                    let selector = DeviceSelector::BusAddress(device.descriptor.address);
+                    self.write_volume(&selector, cap).await?;
                 }
-                HotplugEvent::Disconnected(device_id) => {
-                    log::debug!("Disconnect: {:?}", device_id);
-                    if let Some(s) = self.single {
-                        if device_id == s {
-                            self.single = None;
-                            tx.send(None).expect("disconnect: send failed");
+            }
+        }
+        self.write_setting(selector, gain).await
+    }
+
+    /// Changes a single [`Setting`] and reads it back, ex. `set(selector, Filter::NonOversampling)`.
+    ///
+    /// Settings changing the gain go through [`AsyncMoondrop::set_gain`], see [`Setting::gain`].
+    pub async fn set<S: Setting>(
+        &mut self,
+        selector: &DeviceSelector,
+        value: S,
+    ) -> Result<MoondropInfo> {
+        match value.gain() {
+            Some(gain) => self.set_gain(selector, gain).await,
+            None => self.write_setting(selector, value).await,
+        }
+    }
+
+    async fn write_setting<S: Setting>(
+        &mut self,
+        selector: &DeviceSelector,
+        value: S,
+    ) -> Result<MoondropInfo> {
+        log::debug!("{}: {value}", S::NAME);
+        let cmd = command(S::COMMAND, value.to_u8());
+        let device = self
+            .write(selector, &cmd, |caps| S::supported(caps).contains(&value))
+            .await?;
+        self.confirm(&device, &cmd, S::NAME, value, S::of).await
+    }
+
+    /// Sets the volume.
+    ///
+    /// With [`AsyncMoondrop::set_auto_gain`], `level` is taken as the loudness at Low gain, see
+    /// [`AsyncMoondrop::set_loudness`].
+    pub async fn set_volume(
+        &mut self,
+        selector: &DeviceSelector,
+        level: Volume,
+    ) -> Result<MoondropInfo> {
+        match self.auto_gain {
+            Some(_) => self.set_loudness(selector, level.to_db()).await,
+            None => self.write_volume(selector, level).await,
+        }
+    }
+
+    /// Sets how loud the dongle plays, in dB relative to the loudest volume at Low gain.
+    ///
+    /// High gain goes past 0 dB, by as much as the gain offset (see
+    /// [`AsyncMoondrop::set_gain_offset`]), louder requests are clamped to that. With
+    /// [`AsyncMoondrop::set_auto_gain`] the gain is switched on the way, otherwise the dongle keeps
+    /// its gain and the volume is as close as that gain gets. Reports the volume and gain written.
+    pub async fn set_loudness(
+        &mut self,
+        selector: &DeviceSelector,
+        loudness_db: f32,
+    ) -> Result<MoondropInfo> {
+        let device = self.resolve(selector)?;
+        let offset = self.gain_offset(&device)?;
+        // NaN ends up at the quietest volume like in `Volume::from_db`
+        let loudness_db = loudness_db.max(Volume::MIN.to_db()).min(offset);
+        let gain = device.get_all().await?.gain;
+        let (staged, volume) = match self.auto_gain {
+            Some(auto_gain) => auto_gain.stage(gain, loudness_db, offset),
+            None => (gain, AutoGain::volume_at(gain, loudness_db, offset)),
+        };
+        let selector = DeviceSelector::BusAddress(device.descriptor.address);
+        if staged == gain {
+            return self.write_volume(&selector, volume).await;
+        }
+        log::debug!("auto gain: {loudness_db:+.1} dB is {volume:#} at {staged} gain");
+        let settings = Settings {
+            volume: Some(volume),
+            gain: Some(staged),
+            ..Settings::default()
+        };
+        self.apply(&selector, &settings).await
+    }
+
+    async fn write_volume(
+        &mut self,
+        selector: &DeviceSelector,
+        level: Volume,
+    ) -> Result<MoondropInfo> {
+        let level = self.cap_volume(selector, level).await?;
+        log::debug!("Volume Level: {level} step: {:#04x}", level.step());
+        let payload = level.to_payload();
+        let cmd = command(SET_VOLUME, payload);
+        let device = self
+            .write(selector, &cmd, |caps| caps.volume.contains(&payload))
+            .await?;
+        self.confirm(&device, &cmd, "volume", level, |info| info.volume)
+            .await
+    }
+
+    pub async fn set_filter(
+        &mut self,
+        selector: &DeviceSelector,
+        filter: Filter,
+    ) -> Result<MoondropInfo> {
+        self.write_setting(selector, filter).await
+    }
+
+    pub async fn set_indicator_state(
+        &mut self,
+        selector: &DeviceSelector,
+        indicator_state: IndicatorState,
+    ) -> Result<MoondropInfo> {
+        self.write_setting(selector, indicator_state).await
+    }
+
+    /// Walks the volume to `target` one hardware step at a time, spread over `duration`.
+    ///
+    /// Steps are at least [`ramp::MIN_STEP_INTERVAL`] apart, so short durations take longer than
+    /// asked for. Once `cancel` fires the ramp stops and reports the volume it got to.
+    pub async fn ramp_volume(
+        &mut self,
+        selector: &DeviceSelector,
+        target: Volume,
+        duration: Duration,
+        cancel: &Cancel,
+    ) -> Result<MoondropInfo> {
+        let target = self.cap_volume(selector, target).await?;
+        let device = self.device(selector)?.clone();
+        let selector = DeviceSelector::BusAddress(device.descriptor.address);
+        let current = device.get_volume().await?;
+        let distance = current.step().abs_diff(target.step()) as u32;
+        let interval = (duration / distance.max(1)).max(ramp::MIN_STEP_INTERVAL);
+        log::debug!("ramp: {current} -> {target} in {distance} steps every {interval:?}");
+
+        // cancelling is checked right before every write, the final one included, so nothing
+        // more reaches the dongle once it's cancelled
+        for step in ramp::steps(current.step(), target.step()) {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let cmd = command(SET_VOLUME, step);
+            self.write(&selector, &cmd, |caps| caps.volume.contains(&step))
+                .await?;
+            ramp::sleep(interval).await;
+        }
+        if cancel.is_cancelled() {
+            log::debug!("ramp: cancelled");
+            return self.get_all(&selector).await;
+        }
+        self.write_volume(&selector, target).await
+    }
+
+    /// Changes the volume relative to what the dongle currently reports
+    pub async fn adjust_volume(
+        &mut self,
+        selector: &DeviceSelector,
+        delta: VolumeDelta,
+    ) -> Result<MoondropInfo> {
+        let device = self.device(selector)?.clone();
+        let selector = DeviceSelector::BusAddress(device.descriptor.address);
+        let info = match self.auto_gain {
+            // auto gain moves the loudness, which goes past the loudest volume at Low gain
+            Some(_) => {
+                let offset = self.gain_offset(&device)?;
+                let info = device.get_all().await?;
+                let loudness = AutoGain::loudness_db(info.gain, info.volume, offset);
+                self.set_loudness(&selector, loudness + delta.to_db())
+                    .await?
+            }
+            None => {
+                let volume = device.get_volume().await?.adjust(delta);
+                self.write_volume(&selector, volume).await?
+            }
+        };
+        // changed by hand, there's nothing to unmute to anymore
+        self.mute
+            .forget(&device.descriptor)
+            .map_err(Error::MuteState)?;
+        Ok(info)
+    }
+
+    /// Turns the volume all the way down, remembering the previous volume for
+    /// [`AsyncMoondrop::unmute`] even across processes
+    pub async fn mute(&mut self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        let device = self.device(selector)?.clone();
+        let volume = device.get_volume().await?;
+        let muted = self
+            .mute
+            .get(&device.descriptor)
+            .map_err(Error::MuteState)?;
+        if muted.is_some() && volume == Volume::MIN {
+            log::debug!("mute: already muted");
+            return device.get_all().await;
+        }
+        // remembered first, so the level isn't lost if the process dies right after the write
+        self.mute
+            .remember(&device.descriptor, volume)
+            .map_err(Error::MuteState)?;
+        let selector = DeviceSelector::BusAddress(device.descriptor.address);
+        let result = self.write_volume(&selector, Volume::MIN).await;
+        if result.is_err() {
+            self.mute
+                .forget(&device.descriptor)
+                .map_err(Error::MuteState)?;
+        }
+        result
+    }
+
+    /// Restores the volume the dongle had before [`AsyncMoondrop::mute`], if it is muted
+    pub async fn unmute(&mut self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        let device = self.device(selector)?.clone();
+        let Some(volume) = self
+            .mute
+            .get(&device.descriptor)
+            .map_err(Error::MuteState)?
+        else {
+            log::debug!("unmute: not muted");
+            return device.get_all().await;
+        };
+        let selector = DeviceSelector::BusAddress(device.descriptor.address);
+        let info = self.write_volume(&selector, volume).await?;
+        self.mute
+            .forget(&device.descriptor)
+            .map_err(Error::MuteState)?;
+        Ok(info)
+    }
+
+    /// Whether the dongle is muted, i.e. at [`Volume::MIN`] with a volume to restore
+    pub async fn is_muted(&self, selector: &DeviceSelector) -> Result<bool> {
+        let device = self.device(selector)?;
+        let muted = self
+            .mute
+            .get(&device.descriptor)
+            .map_err(Error::MuteState)?;
+        Ok(muted.is_some() && device.get_volume().await? == Volume::MIN)
+    }
+
+    /// Unmutes a muted dongle and mutes any other
+    pub async fn toggle_mute(&mut self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        match self.is_muted(selector).await? {
+            true => self.unmute(selector).await,
+            false => self.mute(selector).await,
+        }
+    }
+
+    /// Applies every change in `settings`, verifying each write.
+    ///
+    /// Changes lowering the loudness are written before the ones raising it, so lowering the volume
+    /// and switching to high gain never passes through high gain at the old volume. If any write
+    /// fails the dongle is taken back to the settings it had before. The volume is taken as given,
+    /// even with [`AsyncMoondrop::set_keep_loudness`].
+    pub async fn apply(
+        &mut self,
+        selector: &DeviceSelector,
+        settings: &Settings,
+    ) -> Result<MoondropInfo> {
+        let snapshot = self.get_all(selector).await?;
+        // stick to the device the snapshot was taken from
+        let selector = DeviceSelector::BusAddress(snapshot.bus);
+        let changes = settings::plan(&snapshot, settings);
+        log::debug!("apply: {changes:?}");
+
+        let mut info = snapshot.clone();
+        for (i, change) in changes.iter().enumerate() {
+            match self.change(&selector, *change).await {
+                Ok(confirmed) => info = confirmed,
+                Err(error) => {
+                    // the failed write may have gone through regardless, so it is undone too
+                    let mut rollback = None;
+                    for change in changes[..=i].iter().rev() {
+                        let undo = change.undo(&snapshot);
+                        if let Err(e) = self.change(&selector, undo).await {
+                            log::error!("apply: failed to restore {}: {e}", undo.name());
+                            rollback.get_or_insert(Box::new(e));
                         }
                     }
-                    self.devices.remove(&device_id);
-                    log::debug!("devices: {:?}", self.devices);
+                    return Err(Error::Apply {
+                        setting: change.name(),
+                        error: Box::new(error),
+                        rollback,
+                    });
                 }
             }
         }
+        Ok(info)
+    }
+
+    async fn change(&mut self, selector: &DeviceSelector, change: Change) -> Result<MoondropInfo> {
+        match change {
+            Change::Volume(volume) => self.write_volume(selector, volume).await,
+            Change::Filter(filter) => self.set_filter(selector, filter).await,
+            Change::Gain(gain) => self.write_gain(selector, gain).await,
+            Change::IndicatorState(state) => self.set_indicator_state(selector, state).await,
+        }
+    }
+
+    /// Sends an arbitrary vendor command and reads back `read_len` bytes, none if `0`.
+    ///
+    /// Commands other than the known queries are subject to the same model checks as setters.
+    pub async fn raw_command(
+        &self,
+        selector: &DeviceSelector,
+        cmd: &[u8],
+        read_len: u16,
+    ) -> Result<Vec<u8>> {
+        let device = if protocol::is_query(cmd) {
+            self.device(selector)?.clone()
+        } else {
+            self.writable(selector, |_| true)?
+        };
+        if read_len == 0 {
+            device.write(cmd).await?;
+            return Ok(Vec::new());
+        }
+        device.read(cmd, read_len).await
+    }
+
+    /// Follows dongles being attached and detached, starting with [`watch::DeviceEvent::Added`] for
+    /// every dongle already attached.
+    ///
+    /// Hotplug events always come from the real hardware regardless of the backend, which is
+    /// still what reads the dongles. The watcher stops when the returned [`Watch`] is dropped.
+    pub fn watch(&self) -> Result<Watch> {
+        Watch::start(self.clone())
+    }
+
+    /// Reads the dongles matched by `selector` every `interval`, reporting the settings changed
+    /// in between, ex. with the dongle's buttons.
+    ///
+    /// Each dongle is read over its open session, and the first read only sets the baseline.
+    /// The bus is only listed again when a dongle goes away, none matches `selector` or a
+    /// hotplug event reports a new one, like for [`Self::watch`] from the real hardware.
+    /// Changes made through mdrop show up as well. The monitor stops when the returned
+    /// [`Monitor`] is dropped.
+    pub fn monitor(&self, selector: &DeviceSelector, interval: Duration) -> Monitor {
+        Monitor::start(self.clone(), selector.clone(), interval)
+    }
+
+    /// Re-enumerates the bus, sessions of dongles that are still attached are kept open
+    pub fn refresh(&mut self) -> Result<()> {
+        let mut devices = Self::enumerate(self.backend.as_ref())?;
+        for (address, device) in devices.iter_mut() {
+            if let Some(old) = self.devices.get(address)
+                && old.descriptor == device.descriptor
+            {
+                *device = old.clone();
+            }
+        }
+        self.devices = devices;
+        Ok(())
     }
 
-    pub fn detect(&self) -> Vec<MoondropInfo> {
+    fn select(&self, selector: &DeviceSelector) -> Vec<&MoondropDevice> {
         self.devices
             .values()
-            .map(|di| {
-                let name = match di.product_string() {
-                    Some(name) => name.to_string(),
-                    None => match di.product_id() {
-                        DAWN_PRO_PID => "MOONDROP Dawn Pro".to_string(),
-                        _ => "Unknown".to_string(),
-                    },
-                };
-
-                let vol_data = Self::read(di, &GET_VOLUME, 7)[VOLUME_IDX];
-                let vol = Volume::from_payload(vol_data);
-                let bus = format!("{:02}:{:02}", di.bus_number(), di.device_address());
-                let data = Self::read(di, &GET_ANY, 7);
-                MoondropInfo::new(name, bus, vol, &data)
-            })
+            .enumerate()
+            .filter(|(i, device)| selector.matches(*i, device))
+            .map(|(_, device)| device)
             .collect()
     }
 
-    pub fn get_volume(&self) -> Option<Volume> {
-        if let Some(id) = self.single {
-            let di = self.devices.get(&id).expect("Hashmap should be populated");
-            let data = Self::read(di, &GET_VOLUME, 7);
-            return Some(Volume::from_payload(data[VOLUME_IDX]));
+    /// Writes `cmd` to the selected dongle over its open session.
+    ///
+    /// The bus is only re-enumerated when the cached device list is stale, i.e. the selector no
+    /// longer matches or the dongle went away mid-transfer, and the write is then retried once.
+    /// Dongles that aren't a verified model are refused unless `allow_unknown` is set, and
+    /// `supported` is asked whether a known model accepts the value being written.
+    async fn write(
+        &mut self,
+        selector: &DeviceSelector,
+        cmd: &[u8],
+        supported: impl Fn(&Capabilities) -> bool,
+    ) -> Result<MoondropDevice> {
+        let result = match self.writable(selector, &supported) {
+            Ok(device) => device.write(cmd).await.map(|()| device),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(Error::NoDevice | Error::NoMatch(_) | Error::DeviceGone) => {
+                self.refresh()?;
+                let device = self.writable(selector, &supported)?;
+                device.write(cmd).await?;
+                Ok(device)
+            }
+            result => result,
         }
-        None
     }
 
-    pub fn get_filter(&self) -> Option<Filter> {
-        let all = self.get_all();
-        if let Some(info) = all {
-            return Some(info.filter);
-        }
-        None
-    }
-
-    pub fn get_gain(&self) -> Option<Gain> {
-        let all = self.get_all();
-        if let Some(info) = all {
-            return Some(info.gain);
-        }
-        None
-    }
-
-    pub fn get_indicator_state(&self) -> Option<IndicatorState> {
-        let all = self.get_all();
-        if let Some(info) = all {
-            return Some(info.indicator_state);
-        }
-        None
-    }
-
-    pub fn get_all(&self) -> Option<MoondropInfo> {
-        if let Some(id) = self.single {
-            let di = self.devices.get(&id).expect("Hashmap should be populated");
-            let name = match di.product_string() {
-                Some(name) => name.to_string(),
-                None => match di.product_id() {
-                    DAWN_PRO_PID => "MOONDROP Dawn Pro".to_string(),
-                    _ => "Unknown".to_string(),
-                },
-            };
-
-            let vol_data = Self::read(di, &GET_VOLUME, 7)[VOLUME_IDX];
-            let vol = Volume::from_payload(vol_data);
-            let bus = format!("{:02}:{:02}", di.bus_number(), di.device_address());
-            let data = Self::read(di, &GET_ANY, 7);
-            return Some(MoondropInfo::new(name, bus, vol, &data));
-        }
-
-        None
-    }
-
-    pub fn set_gain(&mut self, gain: Gain) {
-        self.devices = Self::refresh();
-        let mut cmd = Vec::from(SET_GAIN);
-        cmd.push(gain as u8);
-        log::debug!("Gain Command: {:?}", cmd);
-        self.devices.iter().for_each(|(_, di)| {
-            Self::write(di, &cmd);
-        });
-    }
-
-    pub fn set_volume(&mut self, level: Volume) {
-        self.devices = Self::refresh();
-        let value = level.to_payload();
-        log::debug!("Volume Level: {level} clamped: {value}");
-        let mut cmd = Vec::from(SET_VOLUME);
-        // FIXME: might be incorrect
-        cmd.push(value);
-        log::debug!("Volume Command: {:?}", cmd);
-        self.devices.iter().for_each(|(_, di)| {
-            Self::write(di, &cmd);
-        });
-    }
-
-    pub fn set_filter(&mut self, filter: Filter) {
-        self.devices = Self::refresh();
-        let mut cmd = Vec::from(SET_FILTER);
-        cmd.push(filter as u8);
-        log::debug!("Filter Command: {:?}", cmd);
-        self.devices.iter().for_each(|(_, di)| {
-            Self::write(di, &cmd);
-        });
-    }
-
-    pub fn set_indicator_state(&mut self, indicator_state: IndicatorState) {
-        self.devices = Self::refresh();
-        let mut cmd = Vec::from(SET_INDICATOR_STATE);
-        cmd.push(indicator_state as u8);
-        log::debug!("IndicatorState Command: {:?}", cmd);
-        self.devices.iter().for_each(|(_, di)| {
-            Self::write(di, &cmd);
-        });
-    }
-
-    fn read(di: &DeviceInfo, cmd: &[u8], length: u16) -> Vec<u8> {
-        let device = di.open().expect("device open failed");
-        let _ = future::block_on(device.control_out(ControlOut {
-            control_type: nusb::transfer::ControlType::Vendor,
-            recipient: nusb::transfer::Recipient::Other,
-            request: REQUEST_ID_WRITE,
-            value: REQUEST_VALUE,
-            index: REQUEST_INDEX,
-            data: cmd,
-        }))
-        .into_result()
-        .expect("write failed");
-        future::block_on(device.control_in(ControlIn {
-            control_type: nusb::transfer::ControlType::Vendor,
-            recipient: nusb::transfer::Recipient::Other,
-            request: REQUEST_ID_READ,
-            value: REQUEST_VALUE,
-            index: REQUEST_INDEX,
-            length,
-        }))
-        .into_result()
-        .expect("read failed")
-    }
-
-    fn write(di: &DeviceInfo, cmd: &[u8]) {
-        let device = di.open().expect("device open failed");
-        let _ = future::block_on(device.control_out(ControlOut {
-            control_type: nusb::transfer::ControlType::Vendor,
-            recipient: nusb::transfer::Recipient::Other,
-            request: REQUEST_ID_WRITE,
-            value: REQUEST_VALUE,
-            index: REQUEST_INDEX,
-            data: cmd,
-        }))
-        .into_result()
-        .expect("write failed");
-    }
-
-    fn refresh() -> HashMap<DeviceId, DeviceInfo> {
-        nusb::list_devices()
-            .unwrap()
-            .filter(|d| d.vendor_id() == MOONDROP_VID)
-            .map(|d| (d.id(), d))
-            .collect()
+    /// Gain offset of `device`, the one set by the user or else its model's
+    fn gain_offset(&self, device: &MoondropDevice) -> Result<f32> {
+        let model = device.descriptor.model();
+        self.gain_offset
+            .or_else(|| model.and_then(|model| model.capabilities.gain_offset_db))
+            .ok_or(Error::UnsupportedSetting {
+                model: model.map_or("this unknown model", |model| model.name),
+            })
+    }
+
+    /// Resolves `selector`, enumerating again if it doesn't match any known dongle
+    fn resolve(&mut self, selector: &DeviceSelector) -> Result<MoondropDevice> {
+        if let Ok(device) = self.device(selector) {
+            return Ok(device.clone());
+        }
+        self.refresh()?;
+        Ok(self.device(selector)?.clone())
+    }
+
+    /// Holds `level` to the cap at the dongle's current gain, clamping or failing as configured
+    async fn cap_volume(&mut self, selector: &DeviceSelector, level: Volume) -> Result<Volume> {
+        if self.cap_override || self.caps.caps().is_empty() {
+            return Ok(level);
+        }
+        let device = self.resolve(selector)?;
+        if !self.caps.covers(&device.descriptor) {
+            return Ok(level);
+        }
+        let gain = device.get_all().await?.gain;
+        let Some(cap) = self.caps.cap(&device.descriptor, gain) else {
+            return Ok(level);
+        };
+        if !level.is_louder(&cap) {
+            return Ok(level);
+        }
+        match self.caps.mode {
+            CapMode::Clamp => {
+                log::warn!("volume {level} is over the {cap} cap at {gain} gain, clamping it");
+                Ok(cap)
+            }
+            CapMode::Fail => Err(Error::OverCap {
+                gain,
+                requested: level,
+                cap,
+            }),
+        }
     }
-}
 
-impl Default for Moondrop {
-    fn default() -> Self {
-        Self::new()
+    /// The dongle and its current volume if that is over the cap at `gain`
+    async fn over_cap(
+        &mut self,
+        selector: &DeviceSelector,
+        gain: Gain,
+    ) -> Result<Option<(MoondropDevice, Volume)>> {
+        if self.cap_override || self.caps.caps().is_empty() {
+            return Ok(None);
+        }
+        let device = self.resolve(selector)?;
+        let Some(cap) = self.caps.cap(&device.descriptor, gain) else {
+            return Ok(None);
+        };
+        let volume = device.get_volume().await?;
+        Ok(volume.is_louder(&cap).then_some((device, volume)))
+    }
+
+    /// Reads the settings back after `cmd` was written, writing it again up to `verify_retries`
+    /// times until the dongle reports `expected`
+    async fn confirm<T: PartialEq + Display>(
+        &self,
+        device: &MoondropDevice,
+        cmd: &[u8],
+        setting: &'static str,
+        expected: T,
+        read_back: impl Fn(&MoondropInfo) -> T,
+    ) -> Result<MoondropInfo> {
+        let mut attempt = 0;
+        loop {
+            let info = device.get_all().await?;
+            let actual = read_back(&info);
+            if actual == expected {
+                return Ok(info);
+            }
+            log::warn!("{setting}: wrote {expected} but the dongle reports {actual}");
+            if attempt == self.verify_retries {
+                return Err(Error::Mismatch {
+                    setting,
+                    expected: expected.to_string(),
+                    actual: actual.to_string(),
+                });
+            }
+            attempt += 1;
+            device.write(cmd).await?;
+        }
+    }
+
+    fn writable(
+        &self,
+        selector: &DeviceSelector,
+        supported: impl Fn(&Capabilities) -> bool,
+    ) -> Result<MoondropDevice> {
+        let device = self.device(selector)?;
+        let descriptor = &device.descriptor;
+        match descriptor.model() {
+            Some(model) if model.verified || self.allow_unknown => {
+                if !supported(&model.capabilities) {
+                    return Err(Error::UnsupportedSetting { model: model.name });
+                }
+            }
+            _ if self.allow_unknown => {
+                log::warn!("{}: writing to an unknown device", descriptor.address);
+            }
+            _ => {
+                return Err(Error::UnsupportedDevice {
+                    vendor_id: descriptor.vendor_id,
+                    product_id: descriptor.product_id,
+                });
+            }
+        }
+        Ok(device.clone())
+    }
+
+    fn enumerate(backend: &dyn Backend) -> Result<BTreeMap<BusAddress, MoondropDevice>> {
+        let devices = backend
+            .enumerate()?
+            .into_iter()
+            .map(|d| (d.descriptor.address, d))
+            .collect();
+        Ok(devices)
     }
 }
 
-impl Hash for Moondrop {
+/// How many dB louder High gain is on `device`, if its model is known to say
+impl Hash for AsyncMoondrop {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.single.hash(state);
+        for address in self.devices.keys() {
+            address.hash(state);
+        }
+    }
+}
+
+/// Blocking access to the attached dongles, a thin wrapper around [`AsyncMoondrop`]
+#[derive(Clone, Debug, Hash)]
+pub struct Moondrop {
+    inner: AsyncMoondrop,
+}
+
+impl Moondrop {
+    /// Talks to the attached hardware, tracing to the pcap named by [`trace::TRACE_ENV`] if set,
+    /// see [`AsyncMoondrop::new`]
+    pub fn new() -> Result<Self> {
+        let inner = AsyncMoondrop::new()?;
+        Ok(Self { inner })
+    }
+
+    /// Talks to the attached hardware, tracing to the pcap at `path`, see
+    /// [`AsyncMoondrop::with_trace`]
+    pub fn with_trace(path: impl AsRef<Path>) -> Result<Self> {
+        let inner = AsyncMoondrop::with_trace(path)?;
+        Ok(Self { inner })
+    }
+
+    /// Creates a `Moondrop` that reaches its dongles through `backend`, see
+    /// [`AsyncMoondrop::with_backend`]
+    pub fn with_backend(backend: impl Backend + 'static) -> Result<Self> {
+        let inner = AsyncMoondrop::with_backend(backend)?;
+        Ok(Self { inner })
+    }
+
+    pub fn devices(&self) -> &BTreeMap<BusAddress, MoondropDevice> {
+        &self.inner.devices
+    }
+
+    /// Re-enumerates the bus, sessions of dongles that are still attached are kept open
+    pub fn refresh(&mut self) -> Result<()> {
+        self.inner.refresh()
+    }
+
+    /// Allows writes to dongles that aren't a verified entry of [`model::MODELS`]
+    pub fn set_allow_unknown(&mut self, allow: bool) {
+        self.inner.set_allow_unknown(allow);
+    }
+
+    /// How many times setters write again when reading back shows the change didn't take effect
+    pub fn set_verify_retries(&mut self, retries: u32) {
+        self.inner.set_verify_retries(retries);
+    }
+
+    /// Where [`Moondrop::mute`] keeps the volume to restore, see [`Moondrop::new`] and
+    /// [`Moondrop::with_backend`] for the default
+    pub fn set_mute_memory(&mut self, memory: MuteMemory) {
+        self.inner.set_mute_memory(memory);
+    }
+
+    /// Volume ceilings enforced by the setters, see [`Moondrop::new`] and
+    /// [`Moondrop::with_backend`] for the default
+    pub fn set_caps(&mut self, caps: VolumeCaps) {
+        self.inner.set_caps(caps);
+    }
+
+    /// Lets the setters go over the volume caps
+    pub fn set_cap_override(&mut self, allow: bool) {
+        self.inner.set_cap_override(allow);
+    }
+
+    /// Makes [`Moondrop::set_gain`] compensate the volume so the loudness stays the same
+    pub fn set_keep_loudness(&mut self, keep: bool) {
+        self.inner.set_keep_loudness(keep);
+    }
+
+    /// How many dB louder High gain is than Low, see [`AsyncMoondrop::set_gain_offset`]
+    pub fn set_gain_offset(&mut self, offset_db: Option<f32>) {
+        self.inner.set_gain_offset(offset_db);
+    }
+
+    /// Lets the volume setters pick the gain, `None` leaves it to the user, see
+    /// [`AsyncMoondrop::set_auto_gain`]
+    pub fn set_auto_gain(&mut self, auto_gain: Option<AutoGain>) -> Result<()> {
+        self.inner.set_auto_gain(auto_gain)
+    }
+
+    /// Hands out the underlying non-blocking API
+    pub fn into_async(self) -> AsyncMoondrop {
+        self.inner
+    }
+
+    /// Follows dongles being attached and detached, see [`AsyncMoondrop::watch`]
+    pub fn watch(&self) -> Result<Watch> {
+        self.inner.watch()
+    }
+
+    /// Reports the settings changed on the dongles, see [`AsyncMoondrop::monitor`]
+    pub fn monitor(&self, selector: &DeviceSelector, interval: Duration) -> Monitor {
+        self.inner.monitor(selector, interval)
+    }
+
+    /// Lists every dongle matched by `selector`
+    pub fn detect(&self, selector: &DeviceSelector) -> Result<Vec<MoondropInfo>> {
+        future::block_on(self.inner.detect(selector))
+    }
+
+    /// Resolves `selector` to exactly one attached dongle
+    pub fn device(&self, selector: &DeviceSelector) -> Result<&MoondropDevice> {
+        self.inner.device(selector)
+    }
+
+    pub fn get_volume(&self, selector: &DeviceSelector) -> Result<Volume> {
+        future::block_on(self.inner.get_volume(selector))
+    }
+
+    /// Reads a single [`Setting`], ex. `get::<Filter>(selector)`
+    pub fn get<S: Setting>(&self, selector: &DeviceSelector) -> Result<S> {
+        future::block_on(self.inner.get(selector))
+    }
+
+    pub fn get_filter(&self, selector: &DeviceSelector) -> Result<Filter> {
+        future::block_on(self.inner.get_filter(selector))
+    }
+
+    pub fn get_gain(&self, selector: &DeviceSelector) -> Result<Gain> {
+        future::block_on(self.inner.get_gain(selector))
+    }
+
+    pub fn get_indicator_state(&self, selector: &DeviceSelector) -> Result<IndicatorState> {
+        future::block_on(self.inner.get_indicator_state(selector))
+    }
+
+    pub fn get_all(&self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        future::block_on(self.inner.get_all(selector))
+    }
+
+    /// Changes a single [`Setting`] and reads it back, ex. `set(selector, Filter::NonOversampling)`
+    pub fn set<S: Setting>(&mut self, selector: &DeviceSelector, value: S) -> Result<MoondropInfo> {
+        future::block_on(self.inner.set(selector, value))
+    }
+
+    pub fn set_gain(&mut self, selector: &DeviceSelector, gain: Gain) -> Result<MoondropInfo> {
+        future::block_on(self.inner.set_gain(selector, gain))
+    }
+
+    pub fn set_volume(&mut self, selector: &DeviceSelector, level: Volume) -> Result<MoondropInfo> {
+        future::block_on(self.inner.set_volume(selector, level))
+    }
+
+    /// Sets how loud the dongle plays, see [`AsyncMoondrop::set_loudness`]
+    pub fn set_loudness(
+        &mut self,
+        selector: &DeviceSelector,
+        loudness_db: f32,
+    ) -> Result<MoondropInfo> {
+        future::block_on(self.inner.set_loudness(selector, loudness_db))
+    }
+
+    pub fn set_filter(
+        &mut self,
+        selector: &DeviceSelector,
+        filter: Filter,
+    ) -> Result<MoondropInfo> {
+        future::block_on(self.inner.set_filter(selector, filter))
+    }
+
+    pub fn set_indicator_state(
+        &mut self,
+        selector: &DeviceSelector,
+        indicator_state: IndicatorState,
+    ) -> Result<MoondropInfo> {
+        future::block_on(self.inner.set_indicator_state(selector, indicator_state))
+    }
+
+    /// Walks the volume to `target` one hardware step at a time, spread over `duration`.
+    ///
+    /// Once `cancel` fires, e.g. from another thread, the ramp stops and reports the volume it got
+    /// to.
+    pub fn ramp_volume(
+        &mut self,
+        selector: &DeviceSelector,
+        target: Volume,
+        duration: Duration,
+        cancel: &Cancel,
+    ) -> Result<MoondropInfo> {
+        future::block_on(self.inner.ramp_volume(selector, target, duration, cancel))
+    }
+
+    /// Changes the volume relative to what the dongle currently reports
+    pub fn adjust_volume(
+        &mut self,
+        selector: &DeviceSelector,
+        delta: VolumeDelta,
+    ) -> Result<MoondropInfo> {
+        future::block_on(self.inner.adjust_volume(selector, delta))
+    }
+
+    /// Turns the volume all the way down, remembering the previous volume for [`Moondrop::unmute`]
+    pub fn mute(&mut self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        future::block_on(self.inner.mute(selector))
+    }
+
+    /// Restores the volume the dongle had before [`Moondrop::mute`], if it is muted
+    pub fn unmute(&mut self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        future::block_on(self.inner.unmute(selector))
+    }
+
+    pub fn is_muted(&self, selector: &DeviceSelector) -> Result<bool> {
+        future::block_on(self.inner.is_muted(selector))
+    }
+
+    /// Unmutes a muted dongle and mutes any other
+    pub fn toggle_mute(&mut self, selector: &DeviceSelector) -> Result<MoondropInfo> {
+        future::block_on(self.inner.toggle_mute(selector))
+    }
+
+    /// Applies every change in `settings`, restoring the previous settings if any write fails
+    pub fn apply(
+        &mut self,
+        selector: &DeviceSelector,
+        settings: &Settings,
+    ) -> Result<MoondropInfo> {
+        future::block_on(self.inner.apply(selector, settings))
+    }
+
+    /// Sends an arbitrary vendor command and reads back `read_len` bytes, none if `0`
+    pub fn raw_command(
+        &self,
+        selector: &DeviceSelector,
+        cmd: &[u8],
+        read_len: u16,
+    ) -> Result<Vec<u8>> {
+        future::block_on(self.inner.raw_command(selector, cmd, read_len))
     }
 }
 
-#[derive(Clone, Debug, Tabled)]
-#[tabled(rename_all = "snake")]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "table",
+    derive(tabled::Tabled),
+    tabled(rename_all = "snake")
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MoondropInfo {
     pub name: String,
-    pub bus: String,
+    pub bus: BusAddress,
     pub volume: Volume,
     pub filter: Filter,
     pub gain: Gain,
@@ -272,17 +1054,14 @@ pub struct MoondropInfo {
 }
 
 impl MoondropInfo {
-    pub fn new(name: String, bus: String, volume: Volume, data: &[u8]) -> Self {
-        let filter = Filter::from(data[FILTER_IDX]);
-        let gain = Gain::from(data[GAIN_IDX]);
-        let state = IndicatorState::from(data[INDICATOR_STATE_IDX]);
+    pub fn new(name: String, bus: BusAddress, state: DeviceState) -> Self {
         Self {
             name,
             bus,
-            volume,
-            filter,
-            gain,
-            indicator_state: state,
+            volume: state.volume,
+            filter: state.filter,
+            gain: state.gain,
+            indicator_state: state.indicator_state,
         }
     }
 }
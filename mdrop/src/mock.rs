@@ -0,0 +1,245 @@
+use std::sync::{Arc, Mutex};
+
+use futures_lite::future;
+
+use crate::device::{BusAddress, DeviceDescriptor, MoondropDevice};
+use crate::error::Result;
+use crate::filter::Filter;
+use crate::gain::Gain;
+use crate::indicator_state::IndicatorState;
+use crate::protocol::{
+    GET_ANY, GET_VOLUME, RESPONSE_LEN, SET_FILTER, SET_GAIN, SET_INDICATOR_STATE, SET_VOLUME,
+    VOLUME_IDX,
+};
+use crate::setting::Setting;
+use crate::transport::{Backend, TransferFuture, Transport};
+use crate::{DAWN_PRO_PID, MOONDROP_VID};
+
+/// Raw register values of an emulated dongle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub volume: u8,
+    pub filter: u8,
+    pub gain: u8,
+    pub indicator_state: u8,
+}
+
+/// What a dongle makes of a vendor command, see [`Registers::handle`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// A query, answered by the next control-in transfer
+    Response(Vec<u8>),
+    /// A setter, applied to the registers
+    Applied,
+    /// A command the dongle doesn't know
+    Unknown,
+}
+
+impl Registers {
+    /// Runs the vendor command `data` against the registers like a Dawn Pro does.
+    ///
+    /// This is the one model of the dongle, shared by [`MemoryTransport`] and `mdrop-emulator`.
+    pub fn handle(&mut self, data: &[u8]) -> Reply {
+        let (cmd, value) = data.split_at(data.len().min(3));
+        match (cmd, value.first().copied()) {
+            (c, _) if c == GET_ANY => Reply::Response(reply(
+                GET_ANY,
+                &[
+                    (Filter::RESPONSE_IDX, self.filter),
+                    (Gain::RESPONSE_IDX, self.gain),
+                    (IndicatorState::RESPONSE_IDX, self.indicator_state),
+                ],
+            )),
+            (c, _) if c == GET_VOLUME => {
+                Reply::Response(reply(GET_VOLUME, &[(VOLUME_IDX, self.volume)]))
+            }
+            (c, Some(v)) if c == SET_FILTER => {
+                self.filter = v;
+                Reply::Applied
+            }
+            (c, Some(v)) if c == SET_GAIN => {
+                self.gain = v;
+                Reply::Applied
+            }
+            (c, Some(v)) if c == SET_VOLUME => {
+                self.volume = v;
+                Reply::Applied
+            }
+            (c, Some(v)) if c == SET_INDICATOR_STATE => {
+                self.indicator_state = v;
+                Reply::Applied
+            }
+            _ => Reply::Unknown,
+        }
+    }
+}
+
+/// Reply echoing `header`, with `fields` at their index and zeroes elsewhere
+fn reply(header: [u8; 3], fields: &[(usize, u8)]) -> Vec<u8> {
+    let mut reply = vec![0; RESPONSE_LEN];
+    reply[..header.len()].copy_from_slice(&header);
+    for &(idx, value) in fields {
+        reply[idx] = value;
+    }
+    reply
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    registers: Registers,
+    response: Vec<u8>,
+}
+
+/// In-memory transport that keeps register state like a Dawn Pro would
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryTransport {
+    pub fn new(registers: Registers) -> Self {
+        Self {
+            state: Mutex::new(MemoryState {
+                registers,
+                response: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.state.lock().unwrap().registers
+    }
+
+    fn handle(&self, data: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.registers.handle(data) {
+            Reply::Response(response) => state.response = response,
+            Reply::Applied => {}
+            Reply::Unknown => log::debug!("memory transport: ignoring command {:02x?}", data),
+        }
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        Box::pin(future::ready(self.handle(data)))
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let mut response = std::mem::take(&mut state.response);
+        response.truncate(length as usize);
+        Box::pin(future::ready(Ok(response)))
+    }
+}
+
+/// Backend exposing a fixed set of [`MemoryTransport`] dongles
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    devices: Vec<MoondropDevice>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches a Dawn Pro backed by `transport` and returns its (fake) bus address
+    pub fn attach(&mut self, transport: Arc<MemoryTransport>) -> BusAddress {
+        let address = BusAddress::new(1, self.devices.len() as u8 + 1);
+        let descriptor = DeviceDescriptor {
+            vendor_id: MOONDROP_VID,
+            product_id: DAWN_PRO_PID,
+            product: Some("MOONDROP Dawn Pro".to_string()),
+            address,
+            port_chain: vec![address.address],
+        };
+        self.devices
+            .push(MoondropDevice::new(descriptor, transport));
+        address
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn enumerate(&self) -> Result<Vec<MoondropDevice>> {
+        Ok(self.devices.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Moondrop;
+    use crate::selector::DeviceSelector;
+    use crate::volume::Volume;
+
+    const REGISTERS: Registers = Registers {
+        volume: 0x40,
+        filter: 0,
+        gain: 0,
+        indicator_state: 0,
+    };
+
+    fn dawn_pro() -> (Arc<MemoryTransport>, BusAddress, Moondrop) {
+        let transport = Arc::new(MemoryTransport::new(REGISTERS));
+        let mut backend = MemoryBackend::new();
+        let address = backend.attach(transport.clone());
+        (transport, address, Moondrop::with_backend(backend).unwrap())
+    }
+
+    #[test]
+    fn get_all_reads_the_registers() {
+        let (transport, address, moondrop) = dawn_pro();
+        transport.state.lock().unwrap().registers = Registers {
+            volume: 0x20,
+            filter: Filter::SlowRollOffLowLatency.to_u8(),
+            gain: Gain::High.to_u8(),
+            indicator_state: IndicatorState::Disabled.to_u8(),
+        };
+
+        let info = moondrop.get_all(&DeviceSelector::Any).unwrap();
+        assert_eq!(info.bus, address);
+        assert_eq!(info.volume, Volume::from_payload(0x20));
+        assert_eq!(info.filter, Filter::SlowRollOffLowLatency);
+        assert_eq!(info.gain, Gain::High);
+        assert_eq!(info.indicator_state, IndicatorState::Disabled);
+    }
+
+    #[test]
+    fn setters_write_the_registers() {
+        let (transport, _, mut moondrop) = dawn_pro();
+        let any = DeviceSelector::Any;
+
+        let info = moondrop
+            .set_volume(&any, Volume::from_payload(0x30))
+            .unwrap();
+        assert_eq!(info.volume, Volume::from_payload(0x30));
+        moondrop
+            .set_filter(&any, Filter::SlowRollOffLowLatency)
+            .unwrap();
+        moondrop.set_gain(&any, Gain::High).unwrap();
+        let info = moondrop
+            .set_indicator_state(&any, IndicatorState::DisabledTemp)
+            .unwrap();
+        assert_eq!(info.indicator_state, IndicatorState::DisabledTemp);
+
+        assert_eq!(
+            transport.registers(),
+            Registers {
+                volume: 0x30,
+                filter: Filter::SlowRollOffLowLatency.to_u8(),
+                gain: Gain::High.to_u8(),
+                indicator_state: IndicatorState::DisabledTemp.to_u8(),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_commands_leave_the_registers_alone() {
+        let mut registers = REGISTERS;
+        assert_eq!(registers.handle(&[0xc0, 0xa5, 0x09, 0x01]), Reply::Unknown);
+        assert_eq!(registers.handle(&SET_VOLUME), Reply::Unknown);
+        assert_eq!(registers, REGISTERS);
+    }
+}
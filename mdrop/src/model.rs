@@ -0,0 +1,62 @@
+use std::ops::RangeInclusive;
+
+use crate::filter::Filter;
+use crate::gain::Gain;
+use crate::indicator_state::IndicatorState;
+use crate::setting::Setting;
+use crate::volume::{VOLUME_MAX, VOLUME_MIN};
+use crate::{DAWN_PRO_PID, MOONDROP_VID};
+
+/// Settings a model lets you change
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub filters: &'static [Filter],
+    pub gains: &'static [Gain],
+    pub indicator_states: &'static [IndicatorState],
+    /// Accepted raw volume payloads, `0x00` being the loudest
+    pub volume: RangeInclusive<u8>,
+    /// How many dB louder [`Gain::High`] is than [`Gain::Low`] at the same volume, if known
+    pub gain_offset_db: Option<f32>,
+}
+
+/// A known Moondrop dongle
+#[derive(Clone, Debug, PartialEq)]
+pub struct Model {
+    pub name: &'static str,
+    /// Product ids this model enumerates with, all under [`MOONDROP_VID`]
+    pub product_ids: &'static [u16],
+    /// Whether the protocol has been checked against real hardware
+    pub verified: bool,
+    pub capabilities: Capabilities,
+}
+
+const DAWN_CAPABILITIES: Capabilities = Capabilities {
+    filters: Filter::ALL,
+    gains: Gain::ALL,
+    indicator_states: IndicatorState::ALL,
+    volume: VOLUME_MAX..=VOLUME_MIN,
+    // not measured yet, keep-loudness and auto gain need `AsyncMoondrop::set_gain_offset` until it is
+    gain_offset_db: None,
+};
+
+pub const DAWN_PRO: Model = Model {
+    name: "MOONDROP Dawn Pro",
+    product_ids: &[DAWN_PRO_PID],
+    verified: true,
+    capabilities: DAWN_CAPABILITIES,
+};
+
+/// Every model mdrop knows about
+pub const MODELS: &[Model] = &[DAWN_PRO];
+
+impl Model {
+    /// Looks up the model enumerating with `vendor_id:product_id`
+    pub fn lookup(vendor_id: u16, product_id: u16) -> Option<&'static Model> {
+        if vendor_id != MOONDROP_VID {
+            return None;
+        }
+        MODELS
+            .iter()
+            .find(|model| model.product_ids.contains(&product_id))
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use futures_lite::{StreamExt, future};
+use nusb::hotplug::HotplugEvent;
+
+use crate::device::{DeviceDescriptor, MoondropDevice};
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::gain::Gain;
+use crate::indicator_state::IndicatorState;
+use crate::selector::DeviceSelector;
+use crate::settings::Settings;
+use crate::volume::Volume;
+use crate::watch::WatchHandle;
+use crate::{AsyncMoondrop, MOONDROP_VID, MoondropInfo};
+
+/// Time between two reads by default, short enough for the volume buttons to feel responsive
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A setting that differs between two reads of a dongle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SettingChange {
+    Volume {
+        old: Volume,
+        new: Volume,
+    },
+    Filter {
+        old: Filter,
+        new: Filter,
+    },
+    Gain {
+        old: Gain,
+        new: Gain,
+    },
+    IndicatorState {
+        old: IndicatorState,
+        new: IndicatorState,
+    },
+}
+
+impl Display for SettingChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingChange::Volume { old, new } => write!(f, "volume: {old} -> {new}"),
+            SettingChange::Filter { old, new } => write!(f, "filter: {old} -> {new}"),
+            SettingChange::Gain { old, new } => write!(f, "gain: {old} -> {new}"),
+            SettingChange::IndicatorState { old, new } => {
+                write!(f, "indicator state: {old} -> {new}")
+            }
+        }
+    }
+}
+
+/// Settings of a dongle changed since it was last read, ex. with its volume buttons
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorEvent {
+    pub device: DeviceDescriptor,
+    pub old: MoondropInfo,
+    pub new: MoondropInfo,
+}
+
+impl MonitorEvent {
+    /// Every setting that changed
+    pub fn changes(&self) -> Vec<SettingChange> {
+        let (old, new) = (&self.old, &self.new);
+        let mut changes = Vec::with_capacity(4);
+        if old.volume != new.volume {
+            changes.push(SettingChange::Volume {
+                old: old.volume,
+                new: new.volume,
+            });
+        }
+        if old.filter != new.filter {
+            changes.push(SettingChange::Filter {
+                old: old.filter,
+                new: new.filter,
+            });
+        }
+        if old.gain != new.gain {
+            changes.push(SettingChange::Gain {
+                old: old.gain,
+                new: new.gain,
+            });
+        }
+        if old.indicator_state != new.indicator_state {
+            changes.push(SettingChange::IndicatorState {
+                old: old.indicator_state,
+                new: new.indicator_state,
+            });
+        }
+        changes
+    }
+}
+
+/// Blocking iterator over the [`MonitorEvent`]s of the polled dongles, see
+/// [`AsyncMoondrop::monitor`].
+///
+/// The dongles are read on a helper thread, which is stopped and joined when the `Monitor` is
+/// dropped.
+#[derive(Debug)]
+pub struct Monitor {
+    events: mpsc::Receiver<MonitorEvent>,
+    handle: WatchHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Monitor {
+    pub(crate) fn start(
+        moondrop: AsyncMoondrop,
+        selector: DeviceSelector,
+        interval: Duration,
+    ) -> Self {
+        let handle = WatchHandle::default();
+        let (tx, events) = mpsc::channel();
+        let stop = handle.clone();
+        let thread = thread::spawn(move || {
+            future::block_on(poll(moondrop, selector, interval, tx, stop));
+        });
+        Self {
+            events,
+            handle,
+            thread: Some(thread),
+        }
+    }
+
+    /// Handle stopping this monitor from another thread, ex. one blocked iterating over it
+    pub fn handle(&self) -> WatchHandle {
+        self.handle.clone()
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = MonitorEvent;
+
+    fn next(&mut self) -> Option<MonitorEvent> {
+        self.events.recv().ok()
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.handle.stop();
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            log::error!("device monitor panicked");
+        }
+    }
+}
+
+enum Wake {
+    Tick,
+    /// A Moondrop dongle got attached
+    Attached,
+    /// The hotplug events ran out
+    Unplugged,
+    Stopped,
+}
+
+async fn poll(
+    mut moondrop: AsyncMoondrop,
+    selector: DeviceSelector,
+    interval: Duration,
+    tx: mpsc::Sender<MonitorEvent>,
+    stop: WatchHandle,
+) {
+    // set up on the polling thread, like the one of the watcher
+    let mut hotplug = nusb::watch_devices()
+        .inspect_err(|e| log::warn!("monitor: failed to watch devices: {e}"))
+        .ok();
+    // last reading by port path, the first one only sets the baseline
+    let mut last: BTreeMap<String, MoondropInfo> = BTreeMap::new();
+    // the bus is only listed again when the known dongles may be out of date
+    let mut stale = true;
+    loop {
+        // sessions of dongles that are still attached are kept open
+        if stale && let Err(e) = moondrop.refresh() {
+            log::warn!("monitor: failed to list devices: {e}");
+        }
+        let devices: Vec<MoondropDevice> =
+            moondrop.select(&selector).into_iter().cloned().collect();
+        // nothing matches, ex. while the dongle isn't attached yet
+        stale = devices.is_empty();
+        let mut current = BTreeMap::new();
+        for device in devices {
+            let port = device.descriptor.port_path();
+            let old = last.remove(&port);
+            let new = match device.get_all().await {
+                Ok(info) => info,
+                Err(e) => {
+                    stale |= matches!(e, Error::DeviceGone);
+                    // keep the baseline, so the change still shows once the read works again
+                    log::debug!("monitor: failed to read {port}: {e}");
+                    current.extend(old.map(|old| (port, old)));
+                    continue;
+                }
+            };
+            if let Some(old) = old
+                && Settings::from(&old) != Settings::from(&new)
+            {
+                let event = MonitorEvent {
+                    device: device.descriptor.clone(),
+                    old,
+                    new: new.clone(),
+                };
+                // nobody is listening anymore
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+            current.insert(port, new);
+        }
+        last = current;
+
+        let stopped = async {
+            stop.stopped().await;
+            Wake::Stopped
+        };
+        // only a new dongle is worth reading before the next tick, other devices are of no
+        // interest and a detached dongle fails its next read
+        let hotplugged = async {
+            match hotplug.as_mut() {
+                Some(watch) => loop {
+                    match watch.next().await {
+                        Some(HotplugEvent::Connected(di)) if di.vendor_id() == MOONDROP_VID => {
+                            break Wake::Attached;
+                        }
+                        Some(_) => {}
+                        None => break Wake::Unplugged,
+                    }
+                },
+                None => future::pending().await,
+            }
+        };
+        let elapsed = async {
+            crate::ramp::sleep(interval).await;
+            Wake::Tick
+        };
+        match future::or(stopped, future::or(hotplugged, elapsed)).await {
+            Wake::Tick => {}
+            // read the new dongle right away
+            Wake::Attached => stale = true,
+            Wake::Unplugged => hotplug = None,
+            Wake::Stopped => break,
+        }
+    }
+    log::debug!("monitor: stopped");
+}
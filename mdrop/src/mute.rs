@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::device::DeviceDescriptor;
+use crate::volume::Volume;
+
+/// Environment variable overriding where the volumes of muted dongles are kept
+pub const MUTE_STATE_ENV: &str = "MDROP_MUTE_STATE";
+
+/// Volume a dongle had before it got muted, remembered per USB port.
+///
+/// Every CLI invocation is a new process, so by default the volumes live in a small text file under
+/// `$XDG_STATE_HOME/mdrop` (or `~/.local/state/mdrop`), one `<port> <step>` line per muted dongle.
+/// Clones share the same memory.
+#[derive(Clone, Debug)]
+pub struct MuteMemory {
+    path: Option<PathBuf>,
+    volumes: Arc<Mutex<BTreeMap<String, Volume>>>,
+}
+
+impl MuteMemory {
+    /// Memory kept in the file at `path`
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            volumes: Arc::default(),
+        }
+    }
+
+    /// Memory that only lasts as long as the process
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            volumes: Arc::default(),
+        }
+    }
+
+    /// Memory in [`MUTE_STATE_ENV`] if set, else in the user's state directory, falling back to
+    /// [`MuteMemory::in_memory`] without a home directory
+    pub fn from_env() -> Self {
+        if let Some(path) = std::env::var_os(MUTE_STATE_ENV) {
+            return Self::file(path);
+        }
+        let state = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            });
+        match state {
+            Some(state) => Self::file(state.join("mdrop").join("muted")),
+            None => Self::in_memory(),
+        }
+    }
+
+    /// Volume `device` had before it got muted, `None` if it isn't muted
+    pub fn get(&self, device: &DeviceDescriptor) -> io::Result<Option<Volume>> {
+        let volumes = self.load()?;
+        Ok(volumes.get(&key(device)).copied())
+    }
+
+    /// Remembers `volume` as what to restore `device` to
+    pub fn remember(&self, device: &DeviceDescriptor, volume: Volume) -> io::Result<()> {
+        let mut volumes = self.load()?;
+        volumes.insert(key(device), volume);
+        self.store(volumes)
+    }
+
+    /// Forgets about `device`, returning the volume it was to be restored to
+    pub fn forget(&self, device: &DeviceDescriptor) -> io::Result<Option<Volume>> {
+        let mut volumes = self.load()?;
+        let volume = volumes.remove(&key(device));
+        if volume.is_some() {
+            self.store(volumes)?;
+        }
+        Ok(volume)
+    }
+
+    fn load(&self) -> io::Result<BTreeMap<String, Volume>> {
+        let Some(path) = &self.path else {
+            return Ok(self.volumes.lock().unwrap().clone());
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e),
+        };
+        let volumes = contents
+            .lines()
+            .filter_map(|line| {
+                let (port, step) = line.split_once(' ')?;
+                match step.trim().parse() {
+                    Ok(step) => Some((port.to_string(), Volume::from_step(step))),
+                    Err(_) => {
+                        log::warn!("{}: ignoring malformed line {line:?}", path.display());
+                        None
+                    }
+                }
+            })
+            .collect();
+        Ok(volumes)
+    }
+
+    fn store(&self, volumes: BTreeMap<String, Volume>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            *self.volumes.lock().unwrap() = volumes;
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents: String = volumes
+            .iter()
+            .map(|(port, volume)| format!("{port} {}\n", volume.step()))
+            .collect();
+        fs::write(path, contents)
+    }
+}
+
+/// The port path, unlike the bus address it stays the same when the dongle is plugged in again
+fn key(device: &DeviceDescriptor) -> String {
+    device.port_path()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BusAddress;
+
+    fn dongle(ports: &[u8]) -> DeviceDescriptor {
+        DeviceDescriptor {
+            vendor_id: crate::MOONDROP_VID,
+            product_id: crate::DAWN_PRO_PID,
+            product: None,
+            address: BusAddress::new(3, 7),
+            port_chain: ports.to_vec(),
+        }
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("mdrop-{name}-{}", std::process::id()))
+            .join("muted")
+    }
+
+    #[test]
+    fn in_memory_round_trip() {
+        let memory = MuteMemory::in_memory();
+        let (a, b) = (dongle(&[1]), dongle(&[2]));
+        memory.remember(&a, Volume::from_step(0x20)).unwrap();
+
+        // clones share the memory
+        let clone = memory.clone();
+        assert_eq!(clone.get(&a).unwrap(), Some(Volume::from_step(0x20)));
+        assert_eq!(clone.get(&b).unwrap(), None);
+        assert_eq!(clone.forget(&a).unwrap(), Some(Volume::from_step(0x20)));
+        assert_eq!(memory.get(&a).unwrap(), None);
+        assert_eq!(memory.forget(&a).unwrap(), None);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let path = state_path("mute-file");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        let (a, b) = (dongle(&[1, 2]), dongle(&[4]));
+
+        // a dongle that isn't muted doesn't create the file
+        assert_eq!(MuteMemory::file(&path).forget(&a).unwrap(), None);
+        assert!(!path.exists());
+
+        let memory = MuteMemory::file(&path);
+        memory.remember(&a, Volume::from_step(0x20)).unwrap();
+        memory.remember(&b, Volume::from_step(0x38)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3-1.2 32\n3-4 56\n");
+
+        // another process sees what this one remembered
+        let other = MuteMemory::file(&path);
+        assert_eq!(other.forget(&a).unwrap(), Some(Volume::from_step(0x20)));
+        assert_eq!(memory.get(&a).unwrap(), None);
+        assert_eq!(memory.get(&b).unwrap(), Some(Volume::from_step(0x38)));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let path = state_path("mute-malformed");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "3-1 loud\ngarbage\n3-2 16\n").unwrap();
+
+        let memory = MuteMemory::file(&path);
+        assert_eq!(memory.get(&dongle(&[1])).unwrap(), None);
+        assert_eq!(
+            memory.get(&dongle(&[2])).unwrap(),
+            Some(Volume::from_step(16))
+        );
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,338 @@
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+/// `LINKTYPE_USB_LINUX`, usbmon packets with the older 48 byte header
+pub const LINKTYPE_USB_LINUX: u32 = 189;
+/// `LINKTYPE_USB_LINUX_MMAPPED`, usbmon packets with the 64 byte header
+pub const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const SNAPLEN: u32 = 65535;
+/// Largest record read whatever the snapshot length claims, usbmon captures use at most 256 KiB
+const MAX_RECORD_LEN: u32 = 256 * 1024;
+
+/// usbmon transfer type of control transfers
+pub const URB_CONTROL: u8 = 2;
+
+/// Whether a [`UsbPacket`] records the submission or the completion of a transfer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Submit,
+    Complete,
+}
+
+impl EventKind {
+    fn tag(self) -> u8 {
+        match self {
+            EventKind::Submit => b'S',
+            EventKind::Complete => b'C',
+        }
+    }
+}
+
+/// A single usbmon event, as Wireshark shows it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsbPacket {
+    /// Identifies the transfer, shared by its submission and completion
+    pub id: u64,
+    pub kind: EventKind,
+    pub transfer_type: u8,
+    /// Endpoint address, bit 7 set for device to host
+    pub endpoint: u8,
+    pub device: u8,
+    pub bus: u16,
+    /// Setup packet, only present on submission of control transfers
+    pub setup: Option<[u8; 8]>,
+    /// Time since the unix epoch
+    pub timestamp: Duration,
+    /// 0 on success, `-EINPROGRESS` on submission or a negated errno
+    pub status: i32,
+    /// Length of the transfer buffer, which may be larger than `data`
+    pub length: u32,
+    pub data: Vec<u8>,
+}
+
+impl UsbPacket {
+    /// Serializes the packet as `LINKTYPE_USB_LINUX_MMAPPED` frame
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64 + self.data.len());
+        buf.extend_from_slice(&self.id.to_le_bytes());
+        buf.push(self.kind.tag());
+        buf.push(self.transfer_type);
+        buf.push(self.endpoint);
+        buf.push(self.device);
+        buf.extend_from_slice(&self.bus.to_le_bytes());
+        buf.push(if self.setup.is_some() { 0 } else { b'-' });
+        buf.push(match (self.data.is_empty(), self.endpoint & 0x80 != 0) {
+            (false, _) => 0,
+            (true, true) => b'<',
+            (true, false) => b'>',
+        });
+        buf.extend_from_slice(&(self.timestamp.as_secs() as i64).to_le_bytes());
+        buf.extend_from_slice(&(self.timestamp.subsec_micros() as i32).to_le_bytes());
+        buf.extend_from_slice(&self.status.to_le_bytes());
+        buf.extend_from_slice(&self.length.to_le_bytes());
+        buf.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.setup.unwrap_or_default());
+        // interval, start_frame, xfer_flags and ndesc, unused for control transfers
+        buf.extend_from_slice(&[0; 16]);
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+}
+
+/// Writes [`UsbPacket`]s into a pcap capture
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the pcap file header
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // thiszone and sigfigs
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_USB_LINUX_MMAPPED.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, packet: &UsbPacket) -> io::Result<()> {
+        let frame = packet.to_bytes();
+        let mut record = Vec::with_capacity(16 + frame.len());
+        record.extend_from_slice(&(packet.timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&packet.timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&frame);
+        self.writer.write_all(&record)?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl UsbPacket {
+    /// Parses a frame of a `LINKTYPE_USB_LINUX` or `LINKTYPE_USB_LINUX_MMAPPED` capture
+    pub fn parse(frame: &[u8], linktype: u32) -> io::Result<Self> {
+        let header_len = match linktype {
+            LINKTYPE_USB_LINUX => 48,
+            LINKTYPE_USB_LINUX_MMAPPED => 64,
+            _ => return Err(invalid(format!("unsupported link type {linktype}"))),
+        };
+        if frame.len() < header_len {
+            return Err(invalid("truncated usbmon header"));
+        }
+        let kind = match frame[8] {
+            b'S' => EventKind::Submit,
+            b'C' => EventKind::Complete,
+            // a failed submission ends the transfer just like a completion does
+            b'E' => EventKind::Complete,
+            tag => return Err(invalid(format!("unknown usbmon event {tag:#04x}"))),
+        };
+        let secs = i64::from_le_bytes(frame[16..24].try_into().unwrap());
+        let micros = i32::from_le_bytes(frame[24..28].try_into().unwrap());
+        let micros = u32::try_from(micros)
+            .ok()
+            .filter(|&micros| micros < 1_000_000)
+            .ok_or_else(|| invalid(format!("invalid timestamp microseconds {micros}")))?;
+        let captured = u32::from_le_bytes(frame[36..40].try_into().unwrap()) as usize;
+        let data = frame
+            .get(header_len..header_len + captured)
+            .unwrap_or(&frame[header_len..]);
+        Ok(Self {
+            id: u64::from_le_bytes(frame[0..8].try_into().unwrap()),
+            kind,
+            transfer_type: frame[9],
+            endpoint: frame[10],
+            device: frame[11],
+            bus: u16::from_le_bytes(frame[12..14].try_into().unwrap()),
+            setup: (frame[14] == 0).then(|| frame[40..48].try_into().unwrap()),
+            timestamp: Duration::new(secs.max(0) as u64, micros * 1000),
+            status: i32::from_le_bytes(frame[28..32].try_into().unwrap()),
+            length: u32::from_le_bytes(frame[32..36].try_into().unwrap()),
+            data: data.to_vec(),
+        })
+    }
+}
+
+/// Reads [`UsbPacket`]s out of a usbmon pcap capture
+#[derive(Debug)]
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    snaplen: u32,
+    linktype: u32,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads and checks the pcap file header
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let big_endian = if magic == PCAP_MAGIC || magic == PCAP_MAGIC_NANOS {
+            false
+        } else if magic.swap_bytes() == PCAP_MAGIC || magic.swap_bytes() == PCAP_MAGIC_NANOS {
+            true
+        } else {
+            return Err(invalid("not a pcap file (pcapng is not supported)"));
+        };
+        let mut reader = Self {
+            reader,
+            big_endian,
+            snaplen: 0,
+            linktype: 0,
+        };
+        reader.snaplen = reader.u32(&header[16..20]);
+        reader.linktype = reader.u32(&header[20..24]);
+        Ok(reader)
+    }
+
+    pub fn linktype(&self) -> u32 {
+        self.linktype
+    }
+
+    /// Reads the next packet, `None` at the end of the capture
+    pub fn next_packet(&mut self) -> io::Result<Option<UsbPacket>> {
+        let mut record = [0; 16];
+        let mut filled = 0;
+        while filled < record.len() {
+            match self.reader.read(&mut record[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match filled {
+            0 => return Ok(None),
+            16 => {}
+            _ => return Err(invalid("truncated record header")),
+        }
+        // the length is untrusted, don't allocate whatever it says
+        let len = self.u32(&record[8..12]);
+        if len > self.snaplen.min(MAX_RECORD_LEN) {
+            return Err(invalid(format!(
+                "record of {len} bytes exceeds the snapshot length {}",
+                self.snaplen
+            )));
+        }
+        let mut frame = vec![0; len as usize];
+        self.reader
+            .read_exact(&mut frame)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => invalid("truncated record"),
+                _ => e,
+            })?;
+        UsbPacket::parse(&frame, self.linktype).map(Some)
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(id: u64, kind: EventKind, data: &[u8]) -> UsbPacket {
+        UsbPacket {
+            id,
+            kind,
+            transfer_type: URB_CONTROL,
+            endpoint: 0x00,
+            device: 7,
+            bus: 3,
+            setup: (kind == EventKind::Submit).then_some([0x43, 0xa0, 0, 0, 0xa0, 0x09, 3, 0]),
+            timestamp: Duration::new(1_700_000_000, 123_000),
+            status: 0,
+            length: data.len() as u32,
+            data: data.to_vec(),
+        }
+    }
+
+    fn capture(packets: &[UsbPacket]) -> Vec<u8> {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for packet in packets {
+            writer.write(packet).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    fn read_all(capture: &[u8]) -> io::Result<Vec<UsbPacket>> {
+        let mut reader = PcapReader::new(capture)?;
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.next_packet()? {
+            packets.push(packet);
+        }
+        Ok(packets)
+    }
+
+    fn error(capture: &[u8]) -> String {
+        let e = read_all(capture).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{e}");
+        e.to_string()
+    }
+
+    #[test]
+    fn round_trips_packets() {
+        let packets = [
+            packet(1, EventKind::Submit, &[0xc0, 0xa5, 0xa3]),
+            packet(1, EventKind::Complete, &[]),
+        ];
+        let capture = capture(&packets);
+        assert_eq!(
+            PcapReader::new(&capture[..]).unwrap().linktype(),
+            LINKTYPE_USB_LINUX_MMAPPED
+        );
+        assert_eq!(read_all(&capture).unwrap(), packets);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let capture = capture(&[packet(1, EventKind::Submit, &[0xc0, 0xa5, 0xa3])]);
+        // cut into the frame, then into the record header
+        assert_eq!(error(&capture[..capture.len() - 1]), "truncated record");
+        assert_eq!(error(&capture[..24 + 8]), "truncated record header");
+    }
+
+    #[test]
+    fn rejects_records_longer_than_the_snapshot_length() {
+        let mut capture = capture(&[packet(1, EventKind::Submit, &[])]);
+        capture[24 + 8..24 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            error(&capture),
+            format!(
+                "record of {} bytes exceeds the snapshot length {SNAPLEN}",
+                u32::MAX
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_microseconds() {
+        let mut capture = capture(&[packet(1, EventKind::Submit, &[])]);
+        // ts_usec of the usbmon header, after the pcap and record headers
+        let micros = 24 + 16 + 24;
+        capture[micros..micros + 4].copy_from_slice(&1_000_000i32.to_le_bytes());
+        assert_eq!(error(&capture), "invalid timestamp microseconds 1000000");
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt::Display;
+
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::gain::Gain;
+use crate::indicator_state::IndicatorState;
+use crate::setting::{self, Setting};
+use crate::volume::{VOLUME_MAX, VOLUME_MIN, Volume};
+
+pub const GET_ANY: [u8; 3] = [0xC0, 0xA5, 0xA3];
+pub const GET_VOLUME: [u8; 3] = [0xC0, 0xA5, 0xA2];
+pub const SET_FILTER: [u8; 3] = [0xC0, 0xA5, 0x01];
+pub const SET_GAIN: [u8; 3] = [0xC0, 0xA5, 0x02];
+pub const SET_VOLUME: [u8; 3] = [0xC0, 0xA5, 0x04];
+pub const SET_INDICATOR_STATE: [u8; 3] = [0xC0, 0xA5, 0x06];
+
+/// Whether `cmd` only queries the dongle, anything else may change its settings
+pub fn is_query(cmd: &[u8]) -> bool {
+    cmd == GET_ANY || cmd == GET_VOLUME
+}
+
+/// Length of every reply, the first three bytes echo the request header
+pub const RESPONSE_LEN: usize = 7;
+
+/// Index of the volume in the reply to [`GET_VOLUME`]
+pub(crate) const VOLUME_IDX: usize = 4;
+
+/// Why a reply from the dongle could not be decoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The reply doesn't have the expected length
+    Length { expected: usize, actual: usize },
+    /// The reply doesn't echo the header of the request it answers
+    Header { expected: [u8; 3] },
+    /// A field holds a value this library doesn't know about
+    UnknownValue { field: &'static str, value: u8 },
+    /// A command with an opcode this library doesn't know about
+    UnknownCommand { opcode: u8 },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Length { expected, actual } => {
+                write!(f, "expected {expected} bytes, got {actual}")
+            }
+            DecodeError::Header { expected } => {
+                write!(f, "expected header {expected:02x?}")
+            }
+            DecodeError::UnknownValue { field, value } => {
+                write!(f, "unknown {field} value {value:#04x}")
+            }
+            DecodeError::UnknownCommand { opcode } => write!(f, "unknown opcode {opcode:#04x}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Settings reported by a dongle
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceState {
+    pub volume: Volume,
+    pub filter: Filter,
+    pub gain: Gain,
+    pub indicator_state: IndicatorState,
+}
+
+impl DeviceState {
+    /// Decodes the replies to [`GET_VOLUME`] and [`GET_ANY`]
+    pub fn decode(volume: &[u8], any: &[u8]) -> Result<Self> {
+        let volume = decode_volume(volume)?;
+        let (filter, gain, indicator_state) = decode_any(any)?;
+        Ok(Self {
+            volume,
+            filter,
+            gain,
+            indicator_state,
+        })
+    }
+}
+
+/// A vendor command, as sent in the data stage of a control-out transfer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    GetAny,
+    GetVolume,
+    SetFilter(Filter),
+    SetGain(Gain),
+    /// Raw volume payload, `0x00` being the loudest
+    SetVolume(u8),
+    SetIndicatorState(IndicatorState),
+}
+
+impl Command {
+    /// Parses the data of a control-out transfer
+    pub fn parse(data: &[u8]) -> std::result::Result<Self, DecodeError> {
+        if data.len() < 3 {
+            return Err(DecodeError::Length {
+                expected: 3,
+                actual: data.len(),
+            });
+        }
+        if data[..2] != GET_ANY[..2] {
+            return Err(DecodeError::Header { expected: GET_ANY });
+        }
+        let header = [data[0], data[1], data[2]];
+        match header {
+            GET_ANY => return Ok(Command::GetAny),
+            GET_VOLUME => return Ok(Command::GetVolume),
+            SET_FILTER | SET_GAIN | SET_VOLUME | SET_INDICATOR_STATE => {}
+            _ => return Err(DecodeError::UnknownCommand { opcode: data[2] }),
+        }
+        let Some(&value) = data.get(3) else {
+            return Err(DecodeError::Length {
+                expected: 4,
+                actual: data.len(),
+            });
+        };
+        match header {
+            SET_FILTER => Filter::try_from(value).map(Command::SetFilter),
+            SET_GAIN => Gain::try_from(value).map(Command::SetGain),
+            SET_INDICATOR_STATE => IndicatorState::try_from(value).map(Command::SetIndicatorState),
+            _ if (VOLUME_MAX..=VOLUME_MIN).contains(&value) => Ok(Command::SetVolume(value)),
+            _ => Err(DecodeError::UnknownValue {
+                field: "volume",
+                value,
+            }),
+        }
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::GetAny => write!(f, "GET_ANY"),
+            Command::GetVolume => write!(f, "GET_VOLUME"),
+            Command::SetFilter(filter) => write!(f, "SET_FILTER {filter}"),
+            Command::SetGain(gain) => write!(f, "SET_GAIN {gain}"),
+            Command::SetVolume(value) => {
+                write!(
+                    f,
+                    "SET_VOLUME {} ({value:#04x})",
+                    Volume::from_payload(*value)
+                )
+            }
+            Command::SetIndicatorState(state) => write!(f, "SET_INDICATOR_STATE {state}"),
+        }
+    }
+}
+
+/// Decodes the reply to [`GET_VOLUME`]
+pub fn decode_volume(data: &[u8]) -> Result<Volume> {
+    check(data, GET_VOLUME)?;
+    let value = data[VOLUME_IDX];
+    if !(VOLUME_MAX..=VOLUME_MIN).contains(&value) {
+        let reason = DecodeError::UnknownValue {
+            field: "volume",
+            value,
+        };
+        return Err(Error::decode(reason, data));
+    }
+    Ok(Volume::from_payload(value))
+}
+
+/// Decodes filter, gain and indicator state out of the reply to [`GET_ANY`]
+pub fn decode_any(data: &[u8]) -> Result<(Filter, Gain, IndicatorState)> {
+    Ok((
+        decode_setting(data)?,
+        decode_setting(data)?,
+        decode_setting(data)?,
+    ))
+}
+
+/// Decodes a single [`Setting`] out of the reply to [`GET_ANY`]
+pub fn decode_setting<S: Setting>(data: &[u8]) -> Result<S> {
+    check(data, GET_ANY)?;
+    setting::from_u8(data[S::RESPONSE_IDX]).map_err(|reason| Error::decode(reason, data))
+}
+
+/// Bytes as space-separated lowercase hex, ex. `c0 a5 a3`
+pub fn hex(data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|b| format!("{b:02x}")).collect();
+    bytes.join(" ")
+}
+
+fn check(data: &[u8], header: [u8; 3]) -> Result<()> {
+    if data.len() != RESPONSE_LEN {
+        let reason = DecodeError::Length {
+            expected: RESPONSE_LEN,
+            actual: data.len(),
+        };
+        return Err(Error::decode(reason, data));
+    }
+    if data[..3] != header {
+        return Err(Error::decode(
+            DecodeError::Header { expected: header },
+            data,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn any_reply(filter: u8, gain: u8, indicator_state: u8) -> [u8; RESPONSE_LEN] {
+        let mut reply = [0; RESPONSE_LEN];
+        reply[..3].copy_from_slice(&GET_ANY);
+        reply[Filter::RESPONSE_IDX] = filter;
+        reply[Gain::RESPONSE_IDX] = gain;
+        reply[IndicatorState::RESPONSE_IDX] = indicator_state;
+        reply
+    }
+
+    fn volume_reply(volume: u8) -> [u8; RESPONSE_LEN] {
+        let mut reply = [0; RESPONSE_LEN];
+        reply[..3].copy_from_slice(&GET_VOLUME);
+        reply[VOLUME_IDX] = volume;
+        reply
+    }
+
+    fn reason<T: std::fmt::Debug>(result: Result<T>) -> DecodeError {
+        match result {
+            Err(Error::Decode { reason, .. }) => reason,
+            other => panic!("expected a decode error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decodes_known_values() {
+        let state = DeviceState::decode(&volume_reply(0x20), &any_reply(1, 1, 2)).unwrap();
+        assert_eq!(state.volume, Volume::from_payload(0x20));
+        assert_eq!(state.filter, Filter::FastRollOffPhaseCompensated);
+        assert_eq!(state.gain, Gain::High);
+        assert_eq!(state.indicator_state, IndicatorState::Disabled);
+    }
+
+    #[test]
+    fn rejects_unknown_values() {
+        assert_eq!(
+            reason(decode_volume(&volume_reply(VOLUME_MIN + 1))),
+            DecodeError::UnknownValue {
+                field: "volume",
+                value: VOLUME_MIN + 1
+            }
+        );
+        assert_eq!(
+            reason(decode_any(&any_reply(0x42, 0, 0))),
+            DecodeError::UnknownValue {
+                field: "filter",
+                value: 0x42
+            }
+        );
+        assert_eq!(
+            reason(decode_setting::<Gain>(&any_reply(0, 2, 0))),
+            DecodeError::UnknownValue {
+                field: "gain",
+                value: 2
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_replies() {
+        assert_eq!(
+            reason(decode_volume(&volume_reply(0x20)[..3])),
+            DecodeError::Length {
+                expected: RESPONSE_LEN,
+                actual: 3
+            }
+        );
+        // a GET_ANY reply where the volume was asked for
+        assert_eq!(
+            reason(decode_volume(&any_reply(0, 0, 0))),
+            DecodeError::Header {
+                expected: GET_VOLUME
+            }
+        );
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse(&GET_ANY), Ok(Command::GetAny));
+        assert_eq!(
+            Command::parse(&[0xC0, 0xA5, 0x02, 0x01]),
+            Ok(Command::SetGain(Gain::High))
+        );
+        assert_eq!(
+            Command::parse(&[0xC0, 0xA5, 0x04, 0x30]),
+            Ok(Command::SetVolume(0x30))
+        );
+        assert_eq!(
+            Command::parse(&[0xC0, 0xA5, 0x04, VOLUME_MIN + 1]),
+            Err(DecodeError::UnknownValue {
+                field: "volume",
+                value: VOLUME_MIN + 1
+            })
+        );
+        assert_eq!(
+            Command::parse(&SET_FILTER),
+            Err(DecodeError::Length {
+                expected: 4,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            Command::parse(&[0xC0, 0xA5, 0x09, 0x00]),
+            Err(DecodeError::UnknownCommand { opcode: 0x09 })
+        );
+        assert_eq!(
+            Command::parse(&[0xC0, 0xA4, 0x02, 0x01]),
+            Err(DecodeError::Header { expected: GET_ANY })
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Shortest time between two volume steps of a ramp, bounding it to 100 steps per second
+pub const MIN_STEP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Stops a running [`crate::AsyncMoondrop::ramp_volume`], clones share the same state
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the ramp before its next step, the volume stays where it got to
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The hardware steps walked from `from` to `to`, both excluded
+pub(crate) fn steps(from: u8, to: u8) -> Vec<u8> {
+    match from < to {
+        true => (from + 1..to).collect(),
+        false => (to + 1..from).rev().collect(),
+    }
+}
+
+/// Resolves after `duration` on any executor, the timers of every sleep share the one `async-io`
+/// driver thread and are cancelled when dropped
+pub(crate) async fn sleep(duration: Duration) {
+    async_io::Timer::after(duration).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_exclude_both_ends() {
+        assert_eq!(steps(0x40, 0x44), [0x41, 0x42, 0x43]);
+        assert_eq!(steps(0x44, 0x40), [0x43, 0x42, 0x41]);
+    }
+
+    #[test]
+    fn no_steps_between_neighbours() {
+        assert!(steps(0x40, 0x40).is_empty());
+        assert!(steps(0x40, 0x41).is_empty());
+        assert!(steps(0x41, 0x40).is_empty());
+    }
+}
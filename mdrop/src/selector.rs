@@ -0,0 +1,164 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::device::{BusAddress, MoondropDevice};
+use crate::error::Error;
+
+/// Picks out which attached dongle a command is directed to
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DeviceSelector {
+    /// Any attached dongle, commands targeting a single device need exactly one to be attached
+    #[default]
+    Any,
+    /// Bus number and device address, ex. `03:02`
+    BusAddress(BusAddress),
+    /// USB port path as used by sysfs, ex. `3-1.2`
+    Port { bus: u8, ports: Vec<u8> },
+    /// Case-insensitive substring of the product name, ex. `dawn pro`
+    Name(String),
+    /// Position in the list printed by `mdrop devices`
+    Index(usize),
+}
+
+impl DeviceSelector {
+    /// Whether `device`, listed at position `index`, is picked by this selector
+    pub fn matches(&self, index: usize, device: &MoondropDevice) -> bool {
+        let descriptor = &device.descriptor;
+        match self {
+            DeviceSelector::Any => true,
+            DeviceSelector::BusAddress(address) => descriptor.address == *address,
+            DeviceSelector::Port { bus, ports } => {
+                descriptor.address.bus == *bus && descriptor.port_chain == *ports
+            }
+            DeviceSelector::Name(name) => descriptor
+                .name()
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+            DeviceSelector::Index(i) => index == *i,
+        }
+    }
+
+    fn parse_port(s: &str) -> Option<Self> {
+        let (bus, path) = s.split_once('-')?;
+        let bus = bus.parse().ok()?;
+        let ports = path
+            .split('.')
+            .map(|p| p.parse().ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(DeviceSelector::Port { bus, ports })
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(Error::InvalidSelector(s.to_string()));
+        }
+        if let Some((bus, address)) = s.split_once(':') {
+            return match (bus.parse(), address.parse()) {
+                (Ok(bus), Ok(address)) => {
+                    Ok(DeviceSelector::BusAddress(BusAddress::new(bus, address)))
+                }
+                _ => Err(Error::InvalidSelector(s.to_string())),
+            };
+        }
+        if let Ok(index) = s.parse() {
+            return Ok(DeviceSelector::Index(index));
+        }
+        if let Some(port) = Self::parse_port(s) {
+            return Ok(port);
+        }
+        Ok(DeviceSelector::Name(s.to_string()))
+    }
+}
+
+impl Display for DeviceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceSelector::Any => write!(f, "any device"),
+            DeviceSelector::BusAddress(address) => write!(f, "{address}"),
+            DeviceSelector::Port { bus, ports } => {
+                let ports: Vec<String> = ports.iter().map(u8::to_string).collect();
+                write!(f, "{bus}-{}", ports.join("."))
+            }
+            DeviceSelector::Name(name) => write!(f, "\"{name}\""),
+            DeviceSelector::Index(index) => write!(f, "#{index}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::device::DeviceDescriptor;
+    use crate::mock::MemoryTransport;
+
+    fn device() -> MoondropDevice {
+        let descriptor = DeviceDescriptor {
+            vendor_id: crate::MOONDROP_VID,
+            product_id: crate::DAWN_PRO_PID,
+            product: Some("MOONDROP Dawn Pro".to_string()),
+            address: BusAddress::new(3, 7),
+            port_chain: vec![1, 2],
+        };
+        MoondropDevice::new(descriptor, Arc::new(MemoryTransport::default()))
+    }
+
+    fn parse(s: &str) -> DeviceSelector {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_each_kind() {
+        assert_eq!(
+            parse("03:07"),
+            DeviceSelector::BusAddress(BusAddress::new(3, 7))
+        );
+        assert_eq!(
+            parse("3-1.2"),
+            DeviceSelector::Port {
+                bus: 3,
+                ports: vec![1, 2]
+            }
+        );
+        assert_eq!(parse(" 1 "), DeviceSelector::Index(1));
+        assert_eq!(
+            parse("dawn pro"),
+            DeviceSelector::Name("dawn pro".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_selectors() {
+        for s in ["", "  ", "3:x", "300:1"] {
+            assert!(
+                matches!(s.parse::<DeviceSelector>(), Err(Error::InvalidSelector(_))),
+                "{s:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in ["03:07", "3-1.2"] {
+            assert_eq!(parse(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn matches_the_right_device() {
+        let device = device();
+        for s in ["03:07", "3-1.2", "DAWN pro", "moondrop", "0"] {
+            assert!(parse(s).matches(0, &device), "{s:?}");
+        }
+        for s in ["03:08", "4:7", "3-1", "3-1.2.1", "dawn 4.4", "1"] {
+            assert!(!parse(s).matches(0, &device), "{s:?}");
+        }
+        assert!(DeviceSelector::Any.matches(5, &device));
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt::{Debug, Display};
+
+use crate::MoondropInfo;
+use crate::error::Error;
+use crate::gain::Gain;
+use crate::model::Capabilities;
+use crate::protocol::DecodeError;
+
+/// A setting with a fixed set of values, changed with its own command and reported in the reply
+/// to [`crate::protocol::GET_ANY`].
+///
+/// [`crate::AsyncMoondrop::get`] and [`crate::AsyncMoondrop::set`] work on any of them, as do the
+/// parsing and decoding of the raw values. The trait doesn't cover the aggregates though: a new
+/// setting still gets its own field in [`MoondropInfo`], [`crate::protocol::DeviceState`] and
+/// [`crate::settings::Settings`], a [`crate::model::Capabilities`] entry, and a variant in the
+/// per-setting enums of [`crate::settings`] and [`crate::monitor`].
+pub trait Setting: Copy + Eq + Debug + Display + Send + Sync + 'static {
+    /// Name of the setting in messages, ex. `indicator state`
+    const NAME: &'static str;
+    /// Header of the command changing it, the value goes right after it
+    const COMMAND: [u8; 3];
+    /// Index of the value in the reply to [`crate::protocol::GET_ANY`]
+    const RESPONSE_IDX: usize;
+    /// Every value, in register order
+    const ALL: &'static [Self];
+
+    /// Raw register value
+    fn to_u8(self) -> u8;
+
+    /// Short code of the value as typed on the command line, ex. `sroll`
+    fn code(self) -> &'static str;
+
+    /// Full kebab-case name of the value, ex. `slow-roll-off-low-latency`
+    fn name(self) -> &'static str {
+        self.code()
+    }
+
+    /// Values a model accepts
+    fn supported(capabilities: &Capabilities) -> &[Self];
+
+    /// The field of `info` holding the value, as decoded from [`Setting::RESPONSE_IDX`]
+    fn of(info: &MoondropInfo) -> Self;
+
+    /// The gain this value switches the dongle to, for settings changing the loudness.
+    ///
+    /// [`crate::AsyncMoondrop::set`] sends those through [`crate::AsyncMoondrop::set_gain`], so the
+    /// volume caps and keep-loudness still apply.
+    fn gain(self) -> Option<Gain> {
+        None
+    }
+}
+
+/// Strict conversion from the raw register value
+pub(crate) fn from_u8<S: Setting>(value: u8) -> Result<S, DecodeError> {
+    S::ALL
+        .iter()
+        .copied()
+        .find(|s| s.to_u8() == value)
+        .ok_or(DecodeError::UnknownValue {
+            field: S::NAME,
+            value,
+        })
+}
+
+/// Parses the code or the name of a value, ignoring case
+pub(crate) fn parse<S: Setting>(s: &str) -> Result<S, Error> {
+    let lower = s.trim().to_lowercase();
+    S::ALL
+        .iter()
+        .copied()
+        .find(|v| v.code() == lower || v.name() == lower)
+        .ok_or_else(|| Error::InvalidValue {
+            setting: S::NAME,
+            value: s.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+    use crate::indicator_state::IndicatorState;
+
+    #[test]
+    fn from_u8_rejects_unknown_values() {
+        assert_eq!(from_u8::<Filter>(4), Ok(Filter::NonOversampling));
+        assert_eq!(from_u8::<Gain>(1), Ok(Gain::High));
+        assert_eq!(
+            from_u8::<Filter>(5),
+            Err(DecodeError::UnknownValue {
+                field: "filter",
+                value: 5
+            })
+        );
+        assert_eq!(
+            from_u8::<IndicatorState>(0xff),
+            Err(DecodeError::UnknownValue {
+                field: "indicator state",
+                value: 0xff
+            })
+        );
+    }
+
+    #[test]
+    fn every_value_round_trips() {
+        fn check<S: Setting>() {
+            for &value in S::ALL {
+                assert_eq!(from_u8::<S>(value.to_u8()), Ok(value));
+                assert_eq!(parse::<S>(value.code()).ok(), Some(value));
+                assert_eq!(parse::<S>(value.name()).ok(), Some(value));
+            }
+        }
+        check::<Filter>();
+        check::<Gain>();
+        check::<IndicatorState>();
+    }
+
+    #[test]
+    fn parses_codes_and_names_ignoring_case() {
+        assert_eq!(
+            parse::<Filter>("SROLL").ok(),
+            Some(Filter::SlowRollOffLowLatency)
+        );
+        assert_eq!(
+            parse::<Filter>(" Slow-Roll-Off-Phase-Compensated ").ok(),
+            Some(Filter::SlowRollOffPhaseCompensated)
+        );
+        assert!(matches!(
+            parse::<Filter>("slow"),
+            Err(Error::InvalidValue { setting: "filter", value }) if value == "slow"
+        ));
+        assert!(parse::<Gain>("").is_err());
+    }
+}
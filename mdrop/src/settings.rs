@@ -0,0 +1,154 @@
+use crate::MoondropInfo;
+use crate::filter::Filter;
+use crate::gain::Gain;
+use crate::indicator_state::IndicatorState;
+use crate::volume::Volume;
+
+/// A set of changes to apply together, `None` leaves the setting untouched
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Settings {
+    pub volume: Option<Volume>,
+    pub filter: Option<Filter>,
+    pub gain: Option<Gain>,
+    pub indicator_state: Option<IndicatorState>,
+}
+
+impl Settings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl From<&MoondropInfo> for Settings {
+    /// Every setting as currently reported, applying it restores that state
+    fn from(info: &MoondropInfo) -> Self {
+        Self {
+            volume: Some(info.volume),
+            filter: Some(info.filter),
+            gain: Some(info.gain),
+            indicator_state: Some(info.indicator_state),
+        }
+    }
+}
+
+/// A single write of [`Settings`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Change {
+    Volume(Volume),
+    Filter(Filter),
+    Gain(Gain),
+    IndicatorState(IndicatorState),
+}
+
+impl Change {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Change::Volume(_) => "volume",
+            Change::Filter(_) => "filter",
+            Change::Gain(_) => "gain",
+            Change::IndicatorState(_) => "indicator state",
+        }
+    }
+
+    /// The change restoring what `info` reports
+    pub(crate) fn undo(&self, info: &MoondropInfo) -> Self {
+        match self {
+            Change::Volume(_) => Change::Volume(info.volume),
+            Change::Filter(_) => Change::Filter(info.filter),
+            Change::Gain(_) => Change::Gain(info.gain),
+            Change::IndicatorState(_) => Change::IndicatorState(info.indicator_state),
+        }
+    }
+}
+
+/// Orders the writes taking `current` to `target` so the output never gets louder on the way:
+/// anything lowering the loudness goes first, anything raising it last.
+pub(crate) fn plan(current: &MoondropInfo, target: &Settings) -> Vec<Change> {
+    let volume = target.volume.filter(|v| *v != current.volume);
+    let gain = target.gain.filter(|g| *g != current.gain);
+    let quieter = volume.filter(|v| current.volume.is_louder(v));
+    let lower_gain = gain.filter(|g| *g == Gain::Low);
+
+    let mut changes = Vec::with_capacity(4);
+    changes.extend(quieter.map(Change::Volume));
+    changes.extend(lower_gain.map(Change::Gain));
+    changes.extend(
+        target
+            .filter
+            .filter(|f| *f != current.filter)
+            .map(Change::Filter),
+    );
+    changes.extend(
+        target
+            .indicator_state
+            .filter(|s| *s != current.indicator_state)
+            .map(Change::IndicatorState),
+    );
+    changes.extend(gain.filter(|_| lower_gain.is_none()).map(Change::Gain));
+    changes.extend(volume.filter(|_| quieter.is_none()).map(Change::Volume));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BusAddress;
+    use crate::protocol::DeviceState;
+
+    fn info(volume: u8, gain: Gain) -> MoondropInfo {
+        let state = DeviceState {
+            volume: Volume::from_payload(volume),
+            filter: Filter::default(),
+            gain,
+            indicator_state: IndicatorState::default(),
+        };
+        MoondropInfo::new("Dawn Pro".to_string(), BusAddress::new(1, 1), state)
+    }
+
+    #[test]
+    fn louder_goes_last() {
+        let current = info(0x40, Gain::Low);
+        let target = Settings {
+            volume: Some(Volume::from_payload(0x20)),
+            filter: Some(Filter::SlowRollOffLowLatency),
+            gain: Some(Gain::High),
+            indicator_state: None,
+        };
+        assert_eq!(
+            plan(&current, &target),
+            [
+                Change::Filter(Filter::SlowRollOffLowLatency),
+                Change::Gain(Gain::High),
+                Change::Volume(Volume::from_payload(0x20)),
+            ]
+        );
+    }
+
+    #[test]
+    fn quieter_goes_first() {
+        let current = info(0x20, Gain::High);
+        let target = Settings {
+            volume: Some(Volume::from_payload(0x40)),
+            filter: None,
+            gain: Some(Gain::Low),
+            indicator_state: Some(IndicatorState::Disabled),
+        };
+        assert_eq!(
+            plan(&current, &target),
+            [
+                Change::Volume(Volume::from_payload(0x40)),
+                Change::Gain(Gain::Low),
+                Change::IndicatorState(IndicatorState::Disabled),
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_settings_are_skipped() {
+        let current = info(0x40, Gain::Low);
+        assert!(plan(&current, &Settings::from(&current)).is_empty());
+        assert!(plan(&current, &Settings::default()).is_empty());
+    }
+}
@@ -0,0 +1,342 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::device::{BusAddress, MoondropDevice};
+use crate::error::{Error, Result};
+use crate::pcap::{EventKind, PcapWriter, URB_CONTROL, UsbPacket};
+use crate::protocol::hex;
+use crate::transport::{
+    Backend, REQUEST_ID_READ, REQUEST_ID_WRITE, REQUEST_INDEX, REQUEST_VALUE, TransferFuture,
+    Transport,
+};
+
+/// Environment variable naming the pcap file [`crate::AsyncMoondrop::new`] traces to
+pub const TRACE_ENV: &str = "MDROP_TRACE";
+
+/// `bmRequestType` of vendor requests to recipient "other"
+const REQUEST_TYPE_OUT: u8 = 0x43;
+const REQUEST_TYPE_IN: u8 = 0xC3;
+
+const EINPROGRESS: i32 = 115;
+const EPIPE: i32 = 32;
+const ENODEV: i32 = 19;
+const EPROTO: i32 = 71;
+
+#[derive(Debug)]
+struct Sinks {
+    pcap: PcapWriter<File>,
+    log: File,
+}
+
+/// Records control transfers into a usbmon pcap and a human-readable hex log
+#[derive(Debug)]
+pub struct Tracer {
+    start: Instant,
+    next_id: AtomicU64,
+    sinks: Mutex<Sinks>,
+}
+
+impl Tracer {
+    /// Traces into the pcap at `path`, the hex log goes next to it with a `.log` extension
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let pcap = PcapWriter::new(File::create(path)?)?;
+        let log = File::create(path.with_extension("log"))?;
+        Ok(Self {
+            start: Instant::now(),
+            next_id: AtomicU64::new(1),
+            sinks: Mutex::new(Sinks { pcap, log }),
+        })
+    }
+
+    fn record(&self, transfer: Transfer<'_>) {
+        if let Err(e) = self.write(&transfer) {
+            log::warn!("failed to write trace: {e}");
+        }
+    }
+
+    fn write(&self, t: &Transfer<'_>) -> io::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (request_type, request, endpoint) = match t.direction {
+            Direction::Out => (REQUEST_TYPE_OUT, REQUEST_ID_WRITE, 0x00),
+            Direction::In => (REQUEST_TYPE_IN, REQUEST_ID_READ, 0x80),
+        };
+        let mut setup = [0; 8];
+        setup[0] = request_type;
+        setup[1] = request;
+        setup[2..4].copy_from_slice(&REQUEST_VALUE.to_le_bytes());
+        setup[4..6].copy_from_slice(&REQUEST_INDEX.to_le_bytes());
+        setup[6..8].copy_from_slice(&t.length.to_le_bytes());
+
+        let timestamp = t.submitted.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let (status, response) = match &t.result {
+            Ok(response) => (0, *response),
+            Err(e) => (-errno(e), &[][..]),
+        };
+        let submit = UsbPacket {
+            id,
+            kind: EventKind::Submit,
+            transfer_type: URB_CONTROL,
+            endpoint,
+            device: t.address.address,
+            bus: t.address.bus as u16,
+            setup: Some(setup),
+            timestamp,
+            status: -EINPROGRESS,
+            length: t.length as u32,
+            data: t.data.to_vec(),
+        };
+        let complete = UsbPacket {
+            kind: EventKind::Complete,
+            setup: None,
+            timestamp: timestamp + t.elapsed,
+            status,
+            // actual length, for successful writes that is the whole payload
+            length: match (t.direction, &t.result) {
+                (Direction::Out, Ok(_)) => t.length as u32,
+                _ => response.len() as u32,
+            },
+            data: response.to_vec(),
+            ..submit.clone()
+        };
+
+        let mut line = format!(
+            "[{:>10.3}ms] {} {:?} {:02x?}",
+            t.started.duration_since(self.start).as_secs_f64() * 1000.0,
+            t.address,
+            t.direction,
+            setup,
+        );
+        match &t.result {
+            Ok(_) if t.direction == Direction::Out => write!(line, " data {}", hex(t.data)),
+            Ok(response) => write!(line, " response {}", hex(response)),
+            Err(e) => write!(line, " failed: {e}"),
+        }
+        .unwrap();
+        writeln!(line, " ({:.3}ms)", t.elapsed.as_secs_f64() * 1000.0).unwrap();
+
+        let mut sinks = self.sinks.lock().unwrap();
+        sinks.pcap.write(&submit)?;
+        sinks.pcap.write(&complete)?;
+        sinks.log.write_all(line.as_bytes())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Out,
+    In,
+}
+
+struct Transfer<'a> {
+    address: BusAddress,
+    direction: Direction,
+    length: u16,
+    data: &'a [u8],
+    result: std::result::Result<&'a [u8], &'a Error>,
+    started: Instant,
+    submitted: SystemTime,
+    elapsed: Duration,
+}
+
+/// [`Transport`] wrapper handing every transfer to a [`Tracer`]
+#[derive(Debug)]
+pub struct TracingTransport {
+    inner: Arc<dyn Transport>,
+    address: BusAddress,
+    tracer: Arc<Tracer>,
+}
+
+impl TracingTransport {
+    pub fn new(inner: Arc<dyn Transport>, address: BusAddress, tracer: Arc<Tracer>) -> Self {
+        Self {
+            inner,
+            address,
+            tracer,
+        }
+    }
+}
+
+impl Transport for TracingTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        Box::pin(async move {
+            let (submitted, started) = (SystemTime::now(), Instant::now());
+            let result = self.inner.control_out(data).await;
+            self.tracer.record(Transfer {
+                address: self.address,
+                direction: Direction::Out,
+                length: data.len() as u16,
+                data,
+                result: result.as_ref().map(|_| &[][..]),
+                started,
+                submitted,
+                elapsed: started.elapsed(),
+            });
+            result
+        })
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let (submitted, started) = (SystemTime::now(), Instant::now());
+            let result = self.inner.control_in(length).await;
+            self.tracer.record(Transfer {
+                address: self.address,
+                direction: Direction::In,
+                length,
+                data: &[],
+                result: result.as_ref().map(Vec::as_slice),
+                started,
+                submitted,
+                elapsed: started.elapsed(),
+            });
+            result
+        })
+    }
+}
+
+/// [`Backend`] wrapper tracing the transfers of every dongle it enumerates
+#[derive(Debug)]
+pub struct TracingBackend {
+    inner: Arc<dyn Backend>,
+    tracer: Arc<Tracer>,
+}
+
+impl TracingBackend {
+    pub fn new(inner: impl Backend + 'static, tracer: Tracer) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            tracer: Arc::new(tracer),
+        }
+    }
+}
+
+impl Backend for TracingBackend {
+    fn enumerate(&self) -> Result<Vec<MoondropDevice>> {
+        let devices = self
+            .inner
+            .enumerate()?
+            .into_iter()
+            .map(|device| {
+                let transport = TracingTransport::new(
+                    device.shared_transport(),
+                    device.descriptor.address,
+                    self.tracer.clone(),
+                );
+                MoondropDevice::new(device.descriptor, Arc::new(transport))
+            })
+            .collect();
+        Ok(devices)
+    }
+}
+
+fn errno(err: &Error) -> i32 {
+    match err {
+        Error::Stall => EPIPE,
+        Error::DeviceGone => ENODEV,
+        _ => EPROTO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::BufReader;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::Moondrop;
+    use crate::mock::{MemoryBackend, MemoryTransport, Registers};
+    use crate::pcap::PcapReader;
+    use crate::protocol::GET_VOLUME;
+    use crate::selector::DeviceSelector;
+
+    fn trace_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mdrop-{name}-{}.pcap", std::process::id()))
+    }
+
+    /// Reads the volume of an in-memory dongle through a tracer writing to `path`
+    fn read_volume(path: &Path) {
+        let registers = Registers {
+            volume: 0x40,
+            ..Registers::default()
+        };
+        let mut backend = MemoryBackend::new();
+        backend.attach(Arc::new(MemoryTransport::new(registers)));
+        let tracer = Tracer::create(path).unwrap();
+        let moondrop = Moondrop::with_backend(TracingBackend::new(backend, tracer)).unwrap();
+        moondrop.get_volume(&DeviceSelector::Any).unwrap();
+    }
+
+    #[test]
+    fn logs_each_transfer() {
+        let path = trace_path("trace-log");
+        read_volume(&path);
+        let log = fs::read_to_string(path.with_extension("log")).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2, "{log}");
+        assert!(
+            lines[0].contains("01:01 Out [43, a0, 00, 00, a0, 09, 03, 00] data c0 a5 a2 ("),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].contains(
+                "01:01 In [c3, a1, 00, 00, a0, 09, 07, 00] response c0 a5 a2 00 40 00 00 ("
+            ),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn writes_a_usbmon_capture() {
+        let path = trace_path("trace-pcap");
+        read_volume(&path);
+        let file = BufReader::new(File::open(&path).unwrap());
+        let mut reader = PcapReader::new(file).unwrap();
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.next_packet().unwrap() {
+            packets.push(packet);
+        }
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("log"));
+
+        let events: Vec<(u64, EventKind, u8, i32, Vec<u8>)> = packets
+            .iter()
+            .map(|p| (p.id, p.kind, p.endpoint, p.status, p.data.clone()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (
+                    1,
+                    EventKind::Submit,
+                    0x00,
+                    -EINPROGRESS,
+                    GET_VOLUME.to_vec()
+                ),
+                (1, EventKind::Complete, 0x00, 0, vec![]),
+                (2, EventKind::Submit, 0x80, -EINPROGRESS, vec![]),
+                (
+                    2,
+                    EventKind::Complete,
+                    0x80,
+                    0,
+                    vec![0xc0, 0xa5, 0xa2, 0x00, 0x40, 0x00, 0x00]
+                ),
+            ]
+        );
+        assert!(packets.iter().all(|p| (p.bus, p.device) == (1, 1)));
+        assert_eq!(packets[0].setup, Some([0x43, 0xa0, 0, 0, 0xa0, 0x09, 3, 0]));
+        assert_eq!(packets[1].setup, None);
+    }
+}
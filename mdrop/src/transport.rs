@@ -0,0 +1,169 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use nusb::transfer::{ControlIn, ControlOut, ControlType, Recipient};
+use nusb::{Device, DeviceInfo};
+
+use crate::MOONDROP_VID;
+use crate::device::{BusAddress, DeviceDescriptor, MoondropDevice};
+use crate::error::{Error, Result};
+
+pub const REQUEST_INDEX: u16 = 0x09A0;
+pub const REQUEST_VALUE: u16 = 0x0000;
+
+pub const REQUEST_ID_WRITE: u8 = 0xA0;
+pub const REQUEST_ID_READ: u8 = 0xA1;
+
+/// Boxed future returned by [`Transport`] methods
+pub type TransferFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Carries the vendor control transfers understood by Moondrop dongles
+pub trait Transport: Debug + Send + Sync {
+    /// Sends `data` in a vendor control-out transfer (request `0xA0`, index `0x09A0`)
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()>;
+
+    /// Reads up to `length` bytes in a vendor control-in transfer (request `0xA1`, index `0x09A0`)
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>>;
+}
+
+/// Source of the dongles a [`crate::Moondrop`] operates on
+pub trait Backend: Debug + Send + Sync {
+    /// Lists the dongles currently reachable through this backend
+    fn enumerate(&self) -> Result<Vec<MoondropDevice>>;
+}
+
+/// Backend talking to real hardware through `nusb`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NusbBackend;
+
+impl NusbBackend {
+    pub fn device(di: DeviceInfo) -> MoondropDevice {
+        let descriptor = DeviceDescriptor {
+            vendor_id: di.vendor_id(),
+            product_id: di.product_id(),
+            product: di.product_string().map(str::to_string),
+            address: BusAddress::new(di.bus_number(), di.device_address()),
+            port_chain: port_chain(&di),
+        };
+        MoondropDevice::new(descriptor, Arc::new(NusbTransport::new(di)))
+    }
+}
+
+/// Hub ports leading to the device, from its sysfs name (`<bus>-<port>.<port>…`)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn port_chain(di: &DeviceInfo) -> Vec<u8> {
+    di.sysfs_path()
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split_once('-'))
+        .and_then(|(_, ports)| ports.split('.').map(|port| port.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// nusb 0.1 only exposes the port chain on Linux
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn port_chain(_di: &DeviceInfo) -> Vec<u8> {
+    Vec::new()
+}
+
+impl Backend for NusbBackend {
+    fn enumerate(&self) -> Result<Vec<MoondropDevice>> {
+        let devices = nusb::list_devices()
+            .map_err(Error::Enumeration)?
+            .filter(|d| d.vendor_id() == MOONDROP_VID)
+            .map(Self::device)
+            .collect();
+        Ok(devices)
+    }
+}
+
+/// Transport over a `nusb` device handle that is opened once and kept for later transfers
+pub struct NusbTransport {
+    info: DeviceInfo,
+    device: Mutex<Option<Device>>,
+}
+
+impl NusbTransport {
+    pub fn new(info: DeviceInfo) -> Self {
+        Self {
+            info,
+            device: Mutex::new(None),
+        }
+    }
+
+    /// Returns the open handle, opening the device first if needed
+    pub fn open(&self) -> Result<Device> {
+        let mut device = self.device.lock().unwrap();
+        if let Some(device) = device.as_ref() {
+            return Ok(device.clone());
+        }
+        log::debug!("opening device {:?}", self.info.id());
+        let opened = self.info.open().map_err(Error::open)?;
+        *device = Some(opened.clone());
+        Ok(opened)
+    }
+
+    /// Drops the open handle, the next transfer opens the device again
+    pub fn close(&self) {
+        self.device.lock().unwrap().take();
+    }
+
+    fn check<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(Error::DeviceGone) = result {
+            self.close();
+        }
+        result
+    }
+}
+
+impl Debug for NusbTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NusbTransport")
+            .field("info", &self.info)
+            .field("open", &self.device.lock().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl Transport for NusbTransport {
+    fn control_out<'a>(&'a self, data: &'a [u8]) -> TransferFuture<'a, ()> {
+        Box::pin(async move {
+            let device = self.open()?;
+            let result = device
+                .control_out(ControlOut {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Other,
+                    request: REQUEST_ID_WRITE,
+                    value: REQUEST_VALUE,
+                    index: REQUEST_INDEX,
+                    data,
+                })
+                .await
+                .into_result()
+                .map(|_| ())
+                .map_err(Error::from);
+            self.check(result)
+        })
+    }
+
+    fn control_in(&self, length: u16) -> TransferFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let device = self.open()?;
+            let result = device
+                .control_in(ControlIn {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Other,
+                    request: REQUEST_ID_READ,
+                    value: REQUEST_VALUE,
+                    index: REQUEST_INDEX,
+                    length,
+                })
+                .await
+                .into_result()
+                .map_err(Error::from);
+            self.check(result)
+        })
+    }
+}
@@ -1,34 +1,273 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
-const VOLUME_MAX: u8 = 0x00;
-const VOLUME_MIN: u8 = 0x70;
+use crate::error::Error;
 
-/// Moondrop Device Volume in Percent (Not Payload)
-#[derive(Clone, Copy, Debug, Default)]
-pub struct Volume(u32);
+pub(crate) const VOLUME_MAX: u8 = 0x00;
+pub(crate) const VOLUME_MIN: u8 = 0x70;
+
+/// Attenuation of a single volume step, 0 dB being the loudest step `0x00`.
+///
+/// The Dawn dongles are built around Cirrus Logic CS43131 DACs, whose digital volume control moves
+/// in 0.5 dB steps (CS43131 datasheet, PCM volume registers). The dongle's `0x00..=0x70` register
+/// is taken to map one to one onto those steps, down to -56 dB.
+pub const DB_PER_STEP: f32 = 0.5;
+
+/// Relative change of a [`Volume`], positive is louder
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeDelta {
+    Percent(i32),
+    Steps(i32),
+    Db(f32),
+}
+
+impl VolumeDelta {
+    /// The change in dB, a percent being worth as many steps as in [`Volume::from_percent`]
+    pub fn to_db(self) -> f32 {
+        match self {
+            VolumeDelta::Percent(percent) => {
+                percent as f32 * VOLUME_MIN as f32 / 100.0 * DB_PER_STEP
+            }
+            VolumeDelta::Steps(steps) => steps as f32 * DB_PER_STEP,
+            VolumeDelta::Db(db) => db,
+        }
+    }
+}
+
+/// Moondrop Device Volume, kept as the hardware step so no level gets lost in conversion.
+///
+/// Steps are the raw register values, from `0x00` (loudest) to `0x70` (quietest), and are what
+/// round-trips through `Volume`. Percentages are only a rounded view on top of them: the 113 steps
+/// don't fit in 101 percentages, so some steps (ex. 5 or 14) come back as a neighbouring step from
+/// [`Volume::from_percent`]. Each step is [`DB_PER_STEP`] of attenuation.
+///
+/// With the `serde` feature it serializes as `{ "percent": 50, "step": 56, "db": -28.0 }`, any one
+/// of the fields is enough to deserialize, `step` being used over `db` and `percent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "VolumeRepr", try_from = "VolumeRepr")
+)]
+pub struct Volume(u8);
 
 impl Volume {
-    pub fn new(level: u32) -> Self {
-        Self(level)
+    pub const MAX: Volume = Volume(VOLUME_MAX);
+    pub const MIN: Volume = Volume(VOLUME_MIN);
+
+    /// Volume at hardware step `step`, clamped to `0x00..=0x70`
+    pub fn from_step(step: u8) -> Self {
+        Self(step.clamp(VOLUME_MAX, VOLUME_MIN))
     }
-    pub fn to_payload(&self) -> u8 {
-        let level = self.0.min(100);
-        let v = VOLUME_MIN as u32 - (level * VOLUME_MIN as u32).div_ceil(100);
-        (v as u8).clamp(VOLUME_MAX, VOLUME_MIN)
+
+    /// Volume closest to `percent`, clamped to `0..=100`
+    pub fn from_percent(percent: u32) -> Self {
+        let attenuation = (100 - percent.min(100)) * VOLUME_MIN as u32;
+        Self((attenuation as f64 / 100.0).round() as u8)
     }
 
     pub fn from_payload(value: u8) -> Self {
-        let val = value.clamp(VOLUME_MAX, VOLUME_MIN) as u32;
-        Self((VOLUME_MIN as u32 - val) * 100 / VOLUME_MIN as u32)
+        Self::from_step(value)
+    }
+
+    pub fn step(&self) -> u8 {
+        self.0
     }
 
-    pub fn inner(&self) -> u32 {
+    /// Volume closest to `db` of attenuation, clamped to what the dongle covers.
+    ///
+    /// `NaN` and infinities aren't a volume, they give the quietest step rather than the `0x00` a
+    /// cast turns them into, which is the loudest.
+    pub fn from_db(db: f32) -> Self {
+        if !db.is_finite() {
+            return Self::MIN;
+        }
+        let steps = (-db / DB_PER_STEP).round();
+        Self(steps.clamp(VOLUME_MAX as f32, VOLUME_MIN as f32) as u8)
+    }
+
+    /// Volume in percent, rounded to the closest one
+    pub fn percent(&self) -> u32 {
+        let level = (VOLUME_MIN - self.0) as u32 * 100;
+        (level as f64 / VOLUME_MIN as f64).round() as u32
+    }
+
+    /// Attenuation in dB, `0.0` at the loudest step and negative below
+    pub fn to_db(&self) -> f32 {
+        (VOLUME_MAX as f32 - self.0 as f32) * DB_PER_STEP
+    }
+
+    pub fn to_payload(&self) -> u8 {
         self.0
     }
+
+    /// Volume changed by `delta`, saturating at either end of the range
+    pub fn adjust(&self, delta: VolumeDelta) -> Self {
+        match delta {
+            VolumeDelta::Percent(percent) => {
+                Self::from_percent((self.percent() as i32).saturating_add(percent).max(0) as u32)
+            }
+            VolumeDelta::Steps(steps) => Self::from_step(
+                (self.0 as i32)
+                    .saturating_sub(steps)
+                    .clamp(0, u8::MAX as i32) as u8,
+            ),
+            VolumeDelta::Db(db) => Self::from_db(self.to_db() + db),
+        }
+    }
+
+    /// Whether `self` is louder than `other`
+    pub fn is_louder(&self, other: &Volume) -> bool {
+        self.0 < other.0
+    }
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self::MIN
+    }
 }
 
+/// Parses a percentage (`60` or `60%`) or an attenuation in dB (`-18dB`), the way the CLI takes
+/// volumes
+impl FromStr for Volume {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let volume = match trimmed
+            .strip_suffix("dB")
+            .or_else(|| trimmed.strip_suffix("db"))
+        {
+            // the dongle only attenuates
+            Some(db) => match db.trim().parse::<f32>() {
+                Ok(db) if db.is_finite() && db <= 0.0 => Some(Volume::from_db(db)),
+                _ => None,
+            },
+            None => match trimmed.strip_suffix('%').unwrap_or(trimmed).parse() {
+                Ok(percent @ 0..=100) => Some(Volume::from_percent(percent)),
+                _ => None,
+            },
+        };
+        volume.ok_or_else(|| Error::InvalidValue {
+            setting: "volume",
+            value: s.to_string(),
+        })
+    }
+}
+
+/// Shows the percentage, or the attenuation in dB with the alternate flag (`{:#}`)
 impl Display for Volume {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:02}%", self.0)
+        match f.alternate() {
+            true => write!(f, "{:.1} dB", self.to_db()),
+            false => write!(f, "{:02}%", self.percent()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct VolumeRepr {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    percent: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    step: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    db: Option<f32>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Volume> for VolumeRepr {
+    fn from(volume: Volume) -> Self {
+        Self {
+            percent: Some(volume.percent()),
+            step: Some(volume.step()),
+            db: Some(volume.to_db()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<VolumeRepr> for Volume {
+    type Error = String;
+
+    fn try_from(repr: VolumeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            VolumeRepr {
+                step: Some(step), ..
+            } if step <= VOLUME_MIN => Ok(Volume::from_step(step)),
+            VolumeRepr {
+                step: Some(step), ..
+            } => Err(format!(
+                "volume step {step} is out of range, expected 0 to {VOLUME_MIN}"
+            )),
+            VolumeRepr { db: Some(db), .. } if db.is_finite() => Ok(Volume::from_db(db)),
+            VolumeRepr { db: Some(db), .. } => Err(format!("volume {db} dB is not a number")),
+            VolumeRepr {
+                percent: Some(percent),
+                ..
+            } if percent <= 100 => Ok(Volume::from_percent(percent)),
+            VolumeRepr {
+                percent: Some(percent),
+                ..
+            } => Err(format!(
+                "volume {percent}% is out of range, expected 0 to 100"
+            )),
+            _ => Err("volume needs one of `step`, `db` or `percent`".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_round_trips() {
+        for step in VOLUME_MAX..=VOLUME_MIN {
+            let volume = Volume::from_step(step);
+            assert_eq!(volume.step(), step);
+            assert_eq!(Volume::from_payload(volume.to_payload()), volume);
+            assert_eq!(Volume::from_db(volume.to_db()), volume);
+        }
+    }
+
+    #[test]
+    fn percent_round_trips() {
+        for percent in 0..=100 {
+            assert_eq!(Volume::from_percent(percent).percent(), percent);
+        }
+    }
+
+    #[test]
+    fn from_db_rejects_non_finite() {
+        assert_eq!(Volume::from_db(f32::NAN), Volume::MIN);
+        assert_eq!(Volume::from_db(f32::INFINITY), Volume::MIN);
+        assert_eq!(Volume::from_db(f32::NEG_INFINITY), Volume::MIN);
+        assert_eq!(Volume::from_db(0.0), Volume::MAX);
+        assert_eq!(Volume::from_db(-28.0), Volume::from_step(56));
+    }
+
+    #[test]
+    fn parses_percent_and_db() {
+        assert_eq!("60".parse::<Volume>().unwrap(), Volume::from_percent(60));
+        assert_eq!(" 60% ".parse::<Volume>().unwrap(), Volume::from_percent(60));
+        assert_eq!("-18dB".parse::<Volume>().unwrap(), Volume::from_db(-18.0));
+        assert_eq!("-18.5db".parse::<Volume>().unwrap(), Volume::from_db(-18.5));
+        assert_eq!("0dB".parse::<Volume>().unwrap(), Volume::MAX);
+        for invalid in ["101", "-1", "60%%", "3dB", "NaNdB", "-infdB", "loud", ""] {
+            assert!(invalid.parse::<Volume>().is_err(), "{invalid:?} parsed");
+        }
+    }
+
+    #[test]
+    fn adjust_saturates() {
+        let volume = Volume::from_percent(50);
+        assert_eq!(volume.adjust(VolumeDelta::Percent(i32::MAX)), Volume::MAX);
+        assert_eq!(volume.adjust(VolumeDelta::Percent(i32::MIN)), Volume::MIN);
+        assert_eq!(volume.adjust(VolumeDelta::Steps(i32::MAX)), Volume::MAX);
+        assert_eq!(volume.adjust(VolumeDelta::Steps(i32::MIN)), Volume::MIN);
+        assert_eq!(volume.adjust(VolumeDelta::Db(f32::MAX)), Volume::MAX);
     }
 }
@@ -0,0 +1,455 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use futures_lite::{StreamExt, future};
+use nusb::DeviceId;
+use nusb::hotplug::{HotplugEvent, HotplugWatch};
+
+use crate::device::{BusAddress, DeviceDescriptor};
+use crate::error::{Error, Result};
+use crate::{AsyncMoondrop, MOONDROP_VID, MoondropInfo};
+
+/// How long a dongle may be gone before it's reported as [`DeviceEvent::Removed`], so one that
+/// re-enumerates (ex. after a firmware reset) doesn't show up as removed and added again
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Change to the set of attached dongles, reported by [`Watch`].
+///
+/// Dongles are told apart by their USB port path, see [`DeviceDescriptor::port_path`], the bus
+/// address changing every time a dongle enumerates.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceEvent {
+    /// A dongle got attached, `info` is `None` when it couldn't be read
+    Added {
+        device: DeviceDescriptor,
+        info: Option<MoondropInfo>,
+    },
+    /// A dongle was detached for longer than [`DEBOUNCE`]
+    Removed { device: DeviceDescriptor },
+    /// A dongle came back within [`DEBOUNCE`] and reads differently, ex. at a new bus address
+    StateChanged {
+        device: DeviceDescriptor,
+        info: MoondropInfo,
+    },
+}
+
+impl DeviceEvent {
+    pub fn device(&self) -> &DeviceDescriptor {
+        match self {
+            DeviceEvent::Added { device, .. }
+            | DeviceEvent::Removed { device }
+            | DeviceEvent::StateChanged { device, .. } => device,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct StopState {
+    stopped: bool,
+    waker: Option<Waker>,
+}
+
+/// Stops a running [`Watch`] or [`crate::monitor::Monitor`] from any thread, clones share the
+/// same state
+#[derive(Clone, Debug, Default)]
+pub struct WatchHandle(Arc<Mutex<StopState>>);
+
+impl WatchHandle {
+    /// Stops the watcher, which then runs out of events
+    pub fn stop(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.stopped = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.lock().unwrap().stopped
+    }
+
+    pub(crate) async fn stopped(&self) {
+        future::poll_fn(|cx| {
+            let mut state = self.0.lock().unwrap();
+            match state.stopped {
+                true => Poll::Ready(()),
+                false => {
+                    state.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+/// Blocking iterator over the [`DeviceEvent`]s of the attached dongles, see
+/// [`AsyncMoondrop::watch`].
+///
+/// The hotplug events are followed on a helper thread, which is stopped and joined when the
+/// `Watch` is dropped.
+#[derive(Debug)]
+pub struct Watch {
+    events: mpsc::Receiver<DeviceEvent>,
+    handle: WatchHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+    pub(crate) fn start(moondrop: AsyncMoondrop) -> Result<Self> {
+        let handle = WatchHandle::default();
+        let (tx, events) = mpsc::channel();
+        let (started_tx, started) = mpsc::channel();
+        let stop = handle.clone();
+        let thread = thread::spawn(move || {
+            // the hotplug watch is set up on the thread using it, so it doesn't need to be `Send`
+            let watch = match nusb::watch_devices().map_err(Error::Enumeration) {
+                Ok(watch) => watch,
+                Err(e) => {
+                    let _ = started_tx.send(Err(e));
+                    return;
+                }
+            };
+            let mut watcher = Watcher::new(moondrop, tx);
+            let _ = started_tx.send(Ok(()));
+            future::block_on(watcher.run(watch, stop));
+        });
+        match started.recv() {
+            Ok(Ok(())) => Ok(Self {
+                events,
+                handle,
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(mpsc::RecvError) => Err(Error::Enumeration(std::io::Error::other(
+                "device watcher thread exited",
+            ))),
+        }
+    }
+
+    /// Handle stopping this watcher from another thread, ex. one blocked iterating over it
+    pub fn handle(&self) -> WatchHandle {
+        self.handle.clone()
+    }
+}
+
+impl Iterator for Watch {
+    type Item = DeviceEvent;
+
+    fn next(&mut self) -> Option<DeviceEvent> {
+        self.events.recv().ok()
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.handle.stop();
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            log::error!("device watcher panicked");
+        }
+    }
+}
+
+/// Dongle known to the watcher
+#[derive(Debug)]
+struct Tracked {
+    device: DeviceDescriptor,
+    info: Option<MoondropInfo>,
+    /// When it got detached, it's only reported as removed once [`DEBOUNCE`] has passed
+    gone: Option<Instant>,
+}
+
+enum Next {
+    Hotplug(Option<HotplugEvent>),
+    Expired,
+    Stopped,
+}
+
+/// Follows the hotplug events of the dongles, `Id` identifies a device across them
+struct Watcher<Id = DeviceId> {
+    moondrop: AsyncMoondrop,
+    tx: mpsc::Sender<DeviceEvent>,
+    /// Dongles by port path
+    tracked: BTreeMap<String, Tracked>,
+    ports: HashMap<Id, String>,
+}
+
+impl Watcher {
+    async fn run(&mut self, mut watch: HotplugWatch, stop: WatchHandle) {
+        // dongles attached before the watch started are reported as added first
+        match nusb::list_devices() {
+            Ok(devices) => {
+                for di in devices.filter(|d| d.vendor_id() == MOONDROP_VID) {
+                    self.connected(di.id(), address(&di)).await;
+                }
+            }
+            Err(e) => log::warn!("watch: failed to list devices: {e}"),
+        }
+        loop {
+            let deadline = self
+                .tracked
+                .values()
+                .filter_map(|tracked| tracked.gone)
+                .min()
+                .map(|gone| gone + DEBOUNCE);
+            let hotplug = async { Next::Hotplug(watch.next().await) };
+            let expired = async {
+                match deadline {
+                    Some(deadline) => {
+                        crate::ramp::sleep(deadline.saturating_duration_since(Instant::now())).await
+                    }
+                    None => future::pending().await,
+                }
+                Next::Expired
+            };
+            let stopped = async {
+                stop.stopped().await;
+                Next::Stopped
+            };
+            let sent = match future::or(stopped, future::or(hotplug, expired)).await {
+                Next::Hotplug(Some(HotplugEvent::Connected(di))) => {
+                    match di.vendor_id() == MOONDROP_VID {
+                        true => self.connected(di.id(), address(&di)).await,
+                        false => true,
+                    }
+                }
+                Next::Hotplug(Some(HotplugEvent::Disconnected(id))) => {
+                    self.disconnected(id);
+                    true
+                }
+                Next::Expired => self.expire(Instant::now()),
+                Next::Hotplug(None) | Next::Stopped => false,
+            };
+            // nobody is listening anymore
+            if !sent {
+                break;
+            }
+        }
+        log::debug!("watch: stopped");
+    }
+}
+
+impl<Id: Hash + Eq> Watcher<Id> {
+    fn new(moondrop: AsyncMoondrop, tx: mpsc::Sender<DeviceEvent>) -> Self {
+        Self {
+            moondrop,
+            tx,
+            tracked: BTreeMap::new(),
+            ports: HashMap::new(),
+        }
+    }
+
+    /// Reports the dongle at `address`, returns whether anybody is still listening
+    async fn connected(&mut self, id: Id, address: BusAddress) -> bool {
+        // go through the backend so the new session is set up like the others
+        if let Err(e) = self.moondrop.refresh() {
+            log::warn!("watch: failed to list devices: {e}");
+        }
+        let Some(device) = self.moondrop.devices.get(&address).cloned() else {
+            log::debug!("watch: {address} is gone already");
+            return true;
+        };
+        let info = device
+            .get_all()
+            .await
+            .inspect_err(|e| log::warn!("watch: failed to read {address}: {e}"))
+            .ok();
+        let port = device.descriptor.port_path();
+        self.ports.insert(id, port.clone());
+        let descriptor = device.descriptor.clone();
+        let event = match self.tracked.remove(&port) {
+            Some(old) => {
+                log::debug!("watch: {port} re-enumerated at {address}");
+                match info.clone() {
+                    Some(info) if old.info.as_ref() != Some(&info) => {
+                        Some(DeviceEvent::StateChanged {
+                            device: descriptor.clone(),
+                            info,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            None => Some(DeviceEvent::Added {
+                device: descriptor.clone(),
+                info: info.clone(),
+            }),
+        };
+        self.tracked.insert(
+            port,
+            Tracked {
+                device: descriptor,
+                info,
+                gone: None,
+            },
+        );
+        event.is_none_or(|event| self.tx.send(event).is_ok())
+    }
+
+    fn disconnected(&mut self, id: Id) {
+        let Some(port) = self.ports.remove(&id) else {
+            return;
+        };
+        if let Some(tracked) = self.tracked.get_mut(&port) {
+            log::debug!("watch: {port} detached");
+            self.moondrop.devices.remove(&tracked.device.address);
+            tracked.gone = Some(Instant::now());
+        }
+    }
+
+    /// Reports the dongles gone for longer than [`DEBOUNCE`] at `now`, returns whether anybody is
+    /// still listening
+    fn expire(&mut self, now: Instant) -> bool {
+        let expired: Vec<String> = self
+            .tracked
+            .iter()
+            .filter(|(_, tracked)| tracked.gone.is_some_and(|gone| now >= gone + DEBOUNCE))
+            .map(|(port, _)| port.clone())
+            .collect();
+        expired.into_iter().all(|port| {
+            let tracked = self
+                .tracked
+                .remove(&port)
+                .expect("expired dongle is tracked");
+            let event = DeviceEvent::Removed {
+                device: tracked.device,
+            };
+            self.tx.send(event).is_ok()
+        })
+    }
+}
+
+fn address(di: &nusb::DeviceInfo) -> BusAddress {
+    BusAddress::new(di.bus_number(), di.device_address())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DAWN_PRO_PID;
+    use crate::device::MoondropDevice;
+    use crate::mock::{MemoryTransport, Registers};
+    use crate::transport::Backend;
+
+    /// Backend whose dongles can be plugged in and out while the watcher runs
+    #[derive(Clone, Debug, Default)]
+    struct Hub(Arc<Mutex<Vec<MoondropDevice>>>);
+
+    impl Hub {
+        /// Plugs the dongle at `transport` into port 1 of bus 1, at `address`
+        fn plug(&self, transport: &Arc<MemoryTransport>, address: u8) -> BusAddress {
+            let address = BusAddress::new(1, address);
+            let descriptor = DeviceDescriptor {
+                vendor_id: MOONDROP_VID,
+                product_id: DAWN_PRO_PID,
+                product: Some("MOONDROP Dawn Pro".to_string()),
+                address,
+                port_chain: vec![1],
+            };
+            let device = MoondropDevice::new(descriptor, transport.clone());
+            self.0.lock().unwrap().push(device);
+            address
+        }
+
+        fn unplug(&self) {
+            self.0.lock().unwrap().clear();
+        }
+    }
+
+    impl Backend for Hub {
+        fn enumerate(&self) -> Result<Vec<MoondropDevice>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    fn watcher() -> (
+        Hub,
+        Arc<MemoryTransport>,
+        Watcher<u32>,
+        mpsc::Receiver<DeviceEvent>,
+    ) {
+        let hub = Hub::default();
+        let transport = Arc::new(MemoryTransport::new(Registers::default()));
+        let moondrop = AsyncMoondrop::with_backend(hub.clone()).unwrap();
+        let (tx, events) = mpsc::channel();
+        (hub, transport, Watcher::new(moondrop, tx), events)
+    }
+
+    #[test]
+    fn re_enumeration_within_the_debounce_is_a_state_change() {
+        let (hub, transport, mut watcher, events) = watcher();
+        let address = hub.plug(&transport, 5);
+        assert!(future::block_on(watcher.connected(1, address)));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(DeviceEvent::Added { info: Some(_), .. })
+        ));
+
+        hub.unplug();
+        watcher.disconnected(1);
+        assert!(watcher.expire(Instant::now()));
+        let address = hub.plug(&transport, 6);
+        assert!(future::block_on(watcher.connected(2, address)));
+
+        match events.try_recv() {
+            Ok(DeviceEvent::StateChanged { device, info }) => {
+                assert_eq!(device.port_path(), "1-1");
+                assert_eq!(info.bus, address);
+            }
+            event => panic!("expected a state change, got {event:?}"),
+        }
+        assert!(watcher.expire(Instant::now() + DEBOUNCE));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn coming_back_unchanged_is_not_reported() {
+        let (hub, transport, mut watcher, events) = watcher();
+        let address = hub.plug(&transport, 5);
+        future::block_on(watcher.connected(1, address));
+        events.try_recv().unwrap();
+
+        hub.unplug();
+        watcher.disconnected(1);
+        hub.plug(&transport, 5);
+        future::block_on(watcher.connected(2, address));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn removed_once_gone_for_the_debounce() {
+        let (hub, transport, mut watcher, events) = watcher();
+        let address = hub.plug(&transport, 5);
+        future::block_on(watcher.connected(1, address));
+        events.try_recv().unwrap();
+
+        hub.unplug();
+        watcher.disconnected(1);
+        // unknown ids, ex. other devices, are ignored
+        watcher.disconnected(7);
+        assert!(watcher.expire(Instant::now()));
+        assert!(events.try_recv().is_err());
+
+        assert!(watcher.expire(Instant::now() + DEBOUNCE));
+        match events.try_recv() {
+            Ok(DeviceEvent::Removed { device }) => assert_eq!(device.address, address),
+            event => panic!("expected a removal, got {event:?}"),
+        }
+        assert!(watcher.tracked.is_empty());
+    }
+
+    #[test]
+    fn stops_once_nobody_listens() {
+        let (hub, transport, mut watcher, events) = watcher();
+        drop(events);
+        let address = hub.plug(&transport, 5);
+        assert!(!future::block_on(watcher.connected(1, address)));
+    }
+}